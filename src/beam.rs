@@ -0,0 +1,57 @@
+use crate::object::EARTH_RADIUS_M;
+use crate::RENDER_RATIO_TO_M;
+use three_d::Vector3;
+
+/// Effective earth radius multiplier for standard atmospheric refraction (the "4/3 earth" model).
+pub const STANDARD_REFRACTION_FACTOR: f32 = 4.0 / 3.0;
+
+/// Height in meters of the beam center above the earth's surface below it for a gate at the
+/// specified slant range and elevation, bending the beam per the effective earth radius model.
+pub fn beam_height_m(slant_range_m: f32, elevation_rad: f32, refraction_factor: f32) -> f32 {
+    let range = slant_range_m as f64;
+    let effective_radius = refraction_factor as f64 * EARTH_RADIUS_M as f64;
+
+    let height = (range.powi(2)
+        + effective_radius.powi(2)
+        + 2.0 * range * effective_radius * (elevation_rad as f64).sin())
+    .sqrt()
+        - effective_radius;
+
+    height as f32
+}
+
+/// Distance in meters along the earth's surface from the radar to the point below a gate.
+pub fn ground_range_m(
+    slant_range_m: f32,
+    elevation_rad: f32,
+    height_m: f32,
+    refraction_factor: f32,
+) -> f32 {
+    let effective_radius = refraction_factor as f64 * EARTH_RADIUS_M as f64;
+
+    let ground_range = effective_radius
+        * ((slant_range_m as f64 * (elevation_rad as f64).cos())
+            / (effective_radius + height_m as f64))
+            .asin();
+
+    ground_range as f32
+}
+
+/// Render position of a sample at the specified azimuth (radians counterclockwise from +x), ground
+/// range, and height, placed on the curved earth mesh whose top is at the radar (the origin).
+pub fn render_position(azimuth_rad: f32, ground_range_m: f32, height_m: f32) -> Vector3<f32> {
+    let earth_radius = EARTH_RADIUS_M as f64;
+    let radius = earth_radius + height_m as f64;
+    let central_angle = ground_range_m as f64 / earth_radius;
+
+    let horizontal = radius * central_angle.sin();
+    let vertical = radius * central_angle.cos() - earth_radius;
+    let azimuth = azimuth_rad as f64;
+    let ratio = RENDER_RATIO_TO_M as f64;
+
+    Vector3::new(
+        (horizontal * azimuth.cos() * ratio) as f32,
+        (vertical * ratio) as f32,
+        (horizontal * azimuth.sin() * ratio) as f32,
+    )
+}
@@ -0,0 +1,199 @@
+use crate::beam::{beam_height_m, ground_range_m, render_position};
+use crate::data::{
+    azimuth_resolution_deg, get_points, moment_data, select_sweeps, specific_differential_phase,
+    sweeps, ColoredPoint, GateGeometry, BEAM_WIDTH_DEG, BELOW_THRESHOLD, MOMENT_FOLDED,
+};
+use crate::param::{DataParams, Moment};
+use crate::result::{Error, Result};
+use nexrad::model::DataFile;
+use std::f32::consts::PI;
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+
+/// Times the parallel `get_points` against the serial baseline on a decoded volume, verifying both
+/// produce identical points.
+pub fn benchmark_pointing(
+    data: &DataFile,
+    data_params: &DataParams,
+    iterations: u32,
+) -> Result<()> {
+    let cancel = CancellationToken::new();
+
+    let mut baseline_points = Vec::new();
+    let baseline = time_iterations(iterations, || {
+        baseline_points = serial_get_points(data, data_params)?;
+        Ok(())
+    })?;
+
+    let mut parallel_points = Vec::new();
+    let parallel = time_iterations(iterations, || {
+        parallel_points = get_points(
+            data,
+            data_params.moment,
+            data_params.threshold,
+            data_params.refraction_factor,
+            data_params.sweep_selection,
+            &cancel,
+        )?;
+        Ok(())
+    })?;
+
+    println!(
+        "Serial: {:.1} ms, parallel: {:.1} ms ({:.2}x) over {} iterations of {} points.",
+        baseline.as_secs_f64() * 1000.0,
+        parallel.as_secs_f64() * 1000.0,
+        baseline.as_secs_f64() / parallel.as_secs_f64(),
+        iterations,
+        parallel_points.len(),
+    );
+
+    if let Some(index) = first_difference(&baseline_points, &parallel_points) {
+        return Err(Error::BenchmarkMismatchError(format!(
+            "parallel points differ from the serial baseline at point {} ({} vs {} points)",
+            index,
+            baseline_points.len(),
+            parallel_points.len(),
+        )));
+    }
+
+    println!("Parallel points are identical to the serial baseline.");
+
+    Ok(())
+}
+
+/// Mean duration of the specified number of runs.
+fn time_iterations(iterations: u32, mut run: impl FnMut() -> Result<()>) -> Result<Duration> {
+    let start = Instant::now();
+    for _ in 0..iterations {
+        run()?;
+    }
+
+    Ok(start.elapsed() / iterations)
+}
+
+/// The index of the first point which differs, comparing values bitwise so NaNs match.
+fn first_difference(expected: &[ColoredPoint], actual: &[ColoredPoint]) -> Option<usize> {
+    let point_bits = |point: &ColoredPoint| {
+        (
+            [point.pos.x, point.pos.y, point.pos.z, point.value].map(f32::to_bits),
+            [
+                point.gate.azimuth_rad,
+                point.gate.elevation_rad,
+                point.gate.range_m,
+                point.gate.azimuth_width_rad,
+                point.gate.beam_width_rad,
+                point.gate.gate_length_m,
+            ]
+            .map(f32::to_bits),
+            point.folded,
+        )
+    };
+
+    expected
+        .iter()
+        .zip(actual)
+        .position(|(expected, actual)| point_bits(expected) != point_bits(actual))
+        .or((expected.len() != actual.len()).then_some(expected.len().min(actual.len())))
+}
+
+/// A serial baseline written like the implementation `get_points` replaced, allocating gate buffers
+/// per radial and decoding gates inline rather than through `get_points`'s helpers, so the
+/// comparison also catches regressions in the shared decoding.
+fn serial_get_points(data: &DataFile, data_params: &DataParams) -> Result<Vec<ColoredPoint>> {
+    let moment = data_params.moment;
+    let refraction_factor = data_params.refraction_factor;
+    let mut points: Vec<ColoredPoint> = Vec::new();
+
+    let sweeps = sweeps(data);
+    for sweep in select_sweeps(&sweeps, data_params.sweep_selection, moment) {
+        for radial in sweep.radials {
+            let azimuth_angle = (radial.header().azm() - 90.0) * (PI / 180.0);
+            let azimuth_width = azimuth_resolution_deg(radial.header().azm_res()) * (PI / 180.0);
+            let elevation_angle = radial.header().elev() * (PI / 180.0);
+
+            let Some(data_moment) = moment_data(radial, moment) else {
+                continue;
+            };
+
+            let gate_length_m = data_moment.data().data_moment_range_sample_interval() as f32;
+            let first_gate_range_m = data_moment.data().data_moment_range() as f32;
+            let gate_count = data_moment.data().number_data_moment_gates() as usize;
+
+            let raw_gates: Vec<u16> = match data_moment.data().data_word_size() {
+                8 => data_moment
+                    .moment_data()
+                    .iter()
+                    .take(gate_count)
+                    .map(|word| *word as u16)
+                    .collect(),
+                16 => data_moment
+                    .moment_data()
+                    .chunks_exact(2)
+                    .take(gate_count)
+                    .map(|word| u16::from_be_bytes([word[0], word[1]]))
+                    .collect(),
+                word_size => {
+                    return Err(Error::DecodeError(format!(
+                        "unsupported {}-bit data words in {} moment",
+                        word_size,
+                        String::from_utf8_lossy(data_moment.data().data_name()),
+                    )))
+                }
+            };
+
+            let mut scaled_gates: Vec<f32> = Vec::new();
+            for raw_gate in raw_gates {
+                if raw_gate == 0 {
+                    scaled_gates.push(BELOW_THRESHOLD);
+                } else if raw_gate == 1 {
+                    scaled_gates.push(MOMENT_FOLDED);
+                } else {
+                    let scale = data_moment.data().scale();
+                    let offset = data_moment.data().offset();
+
+                    scaled_gates.push(if scale == 0.0 {
+                        raw_gate as f32
+                    } else {
+                        (raw_gate as f32 - offset) / scale
+                    });
+                }
+            }
+
+            if moment == Moment::SpecificDifferentialPhase {
+                let mut kdp_gates = Vec::new();
+                specific_differential_phase(&scaled_gates, gate_length_m / 1000.0, &mut kdp_gates);
+                scaled_gates = kdp_gates;
+            }
+
+            for (gate_index, scaled_gate) in scaled_gates.into_iter().enumerate() {
+                let folded = scaled_gate == MOMENT_FOLDED;
+                if folded
+                    || scaled_gate != BELOW_THRESHOLD
+                        && moment.passes_threshold(scaled_gate, data_params.threshold)
+                {
+                    let gate = GateGeometry {
+                        azimuth_rad: azimuth_angle,
+                        elevation_rad: elevation_angle,
+                        range_m: first_gate_range_m + gate_index as f32 * gate_length_m,
+                        azimuth_width_rad: azimuth_width,
+                        beam_width_rad: BEAM_WIDTH_DEG * (PI / 180.0),
+                        gate_length_m,
+                    };
+
+                    let height_m = beam_height_m(gate.range_m, elevation_angle, refraction_factor);
+                    let ground_range_m =
+                        ground_range_m(gate.range_m, elevation_angle, height_m, refraction_factor);
+                    let position = render_position(azimuth_angle, ground_range_m, height_m);
+
+                    points.push(if folded {
+                        ColoredPoint::new_folded(position, gate)
+                    } else {
+                        ColoredPoint::new(position, gate, scaled_gate)
+                    });
+                }
+            }
+        }
+    }
+
+    Ok(points)
+}
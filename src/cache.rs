@@ -0,0 +1,226 @@
+use crate::result::Result;
+use chrono::{NaiveDate, Utc};
+use nexrad::file::FileMetadata;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const INDEX_FILE_NAME: &str = "index.tsv";
+
+/// A downloaded volume stored in the cache directory.
+#[derive(Clone, Debug)]
+pub struct CacheEntry {
+    pub site: String,
+    pub date: NaiveDate,
+    pub identifier: String,
+    pub size: u64,
+    /// Milliseconds since the Unix epoch when this entry was last written or read.
+    pub last_access: i64,
+}
+
+/// An on-disk cache of downloaded Archive II files, indexed by site/date/identifier and bounded by
+/// total size with least-recently-used eviction.
+pub struct Cache {
+    dir: PathBuf,
+    max_bytes: u64,
+    entries: Vec<CacheEntry>,
+}
+
+impl Cache {
+    /// Opens (creating if needed) the cache in the specified directory, dropping index entries whose
+    /// files are missing or do not match their recorded size.
+    pub fn open(dir: &Path, max_bytes: u64) -> Result<Self> {
+        fs::create_dir_all(dir)?;
+
+        let mut cache = Cache {
+            dir: dir.to_path_buf(),
+            max_bytes,
+            entries: Vec::new(),
+        };
+
+        if let Ok(index) = fs::read_to_string(cache.index_path()) {
+            cache.entries = index.lines().filter_map(parse_entry).collect();
+        }
+
+        let entries = std::mem::take(&mut cache.entries);
+        for entry in entries {
+            if cache.is_intact(&entry) {
+                cache.entries.push(entry);
+            } else {
+                println!(
+                    "Dropping missing or truncated cache file {}.",
+                    entry.identifier
+                );
+                let _ = fs::remove_file(cache.file_path(&entry.identifier));
+            }
+        }
+
+        cache.evict(None)?;
+        cache.save_index()?;
+
+        Ok(cache)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn max_bytes(&self) -> u64 {
+        self.max_bytes
+    }
+
+    pub fn entries(&self) -> &[CacheEntry] {
+        &self.entries
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        self.entries.iter().map(|entry| entry.size).sum()
+    }
+
+    /// Reads a cached file's contents, or None if it is not cached or its file is truncated.
+    pub fn get(&mut self, meta: &FileMetadata) -> Result<Option<Vec<u8>>> {
+        let Some(index) = self.position(meta.identifier()) else {
+            return Ok(None);
+        };
+
+        if !self.is_intact(&self.entries[index]) {
+            println!("Cached file {} is truncated.", meta.identifier());
+            self.remove(meta.identifier())?;
+            return Ok(None);
+        }
+
+        let data = fs::read(self.file_path(meta.identifier()))?;
+
+        self.entries[index].last_access = Utc::now().timestamp_millis();
+        self.save_index()?;
+
+        Ok(Some(data))
+    }
+
+    /// Writes a downloaded file into the cache, evicting the least-recently-used entries if the
+    /// cache exceeds its size limit.
+    pub fn insert(&mut self, meta: &FileMetadata, data: &[u8]) -> Result<()> {
+        // Write to a temporary file first so an interrupted write is never mistaken for a volume
+        let path = self.file_path(meta.identifier());
+        let partial_path = path.with_extension("part");
+        fs::write(&partial_path, data)?;
+        fs::rename(&partial_path, &path)?;
+
+        if let Some(index) = self.position(meta.identifier()) {
+            self.entries.remove(index);
+        }
+
+        self.entries.push(CacheEntry {
+            site: meta.site().to_string(),
+            date: *meta.date(),
+            identifier: meta.identifier().to_string(),
+            size: data.len() as u64,
+            last_access: Utc::now().timestamp_millis(),
+        });
+
+        self.evict(Some(meta.identifier()))?;
+        self.save_index()
+    }
+
+    /// Removes a single entry and its file, e.g. after it failed to decompress.
+    pub fn remove(&mut self, identifier: &str) -> Result<()> {
+        if let Some(index) = self.position(identifier) {
+            self.entries.remove(index);
+            remove_if_exists(&self.file_path(identifier))?;
+            self.save_index()?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes every entry and its file.
+    pub fn clear(&mut self) -> Result<()> {
+        for entry in std::mem::take(&mut self.entries) {
+            remove_if_exists(&self.file_path(&entry.identifier))?;
+        }
+
+        self.save_index()
+    }
+
+    /// Evicts least-recently-used entries until the cache fits its size limit, never evicting the
+    /// specified entry.
+    fn evict(&mut self, keep: Option<&str>) -> Result<()> {
+        while self.total_bytes() > self.max_bytes {
+            let oldest = self
+                .entries
+                .iter()
+                .filter(|entry| Some(entry.identifier.as_str()) != keep)
+                .min_by_key(|entry| entry.last_access)
+                .map(|entry| entry.identifier.clone());
+
+            let Some(identifier) = oldest else {
+                break;
+            };
+
+            println!("Evicting {} from cache.", identifier);
+            let index = self.position(&identifier).unwrap();
+            self.entries.remove(index);
+            remove_if_exists(&self.file_path(&identifier))?;
+        }
+
+        Ok(())
+    }
+
+    fn is_intact(&self, entry: &CacheEntry) -> bool {
+        fs::metadata(self.file_path(&entry.identifier))
+            .map(|metadata| metadata.len() == entry.size)
+            .unwrap_or(false)
+    }
+
+    fn position(&self, identifier: &str) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|entry| entry.identifier == identifier)
+    }
+
+    fn file_path(&self, identifier: &str) -> PathBuf {
+        self.dir.join(identifier)
+    }
+
+    fn index_path(&self) -> PathBuf {
+        self.dir.join(INDEX_FILE_NAME)
+    }
+
+    fn save_index(&self) -> Result<()> {
+        let index = self
+            .entries
+            .iter()
+            .map(|entry| {
+                format!(
+                    "{}\t{}\t{}\t{}\t{}\n",
+                    entry.site, entry.date, entry.identifier, entry.size, entry.last_access
+                )
+            })
+            .collect::<String>();
+
+        fs::write(self.index_path(), index)?;
+
+        Ok(())
+    }
+}
+
+fn parse_entry(line: &str) -> Option<CacheEntry> {
+    let parts = line.split('\t').collect::<Vec<_>>();
+    if parts.len() != 5 {
+        return None;
+    }
+
+    Some(CacheEntry {
+        site: parts[0].to_string(),
+        date: parts[1].parse().ok()?,
+        identifier: parts[2].to_string(),
+        size: parts[3].parse().ok()?,
+        last_access: parts[4].parse().ok()?,
+    })
+}
+
+fn remove_if_exists(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+    }
+}
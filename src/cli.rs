@@ -0,0 +1,382 @@
+use crate::beam::STANDARD_REFRACTION_FACTOR;
+use crate::cache::Cache;
+use crate::color_table::{select_color_table, ColorTable};
+use crate::param::{
+    ClusteringMode, DataParams, DataSource, DensityColormap, GateRenderMode, GridInterpolation,
+    GridParams, InteractionMode, Moment, PointColorMode, SamplingMode, ScanSelection,
+    SweepSelection, TransferPreset, VisParams,
+};
+use crate::transfer::TransferFunction;
+use chrono::{NaiveDate, NaiveTime};
+use clap::Parser;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+const BYTES_PER_MB: u64 = 1024 * 1024;
+
+/// Largest cache size whose byte count fits in a u64.
+const MAX_CACHE_SIZE_MB: u64 = u64::MAX / BYTES_PER_MB;
+
+/// Command-line arguments used to populate the initial data and visualization parameters.
+#[derive(Parser, Debug)]
+#[command(
+    version,
+    about = "Interactive 3D volumetric rendering of NEXRAD radar data"
+)]
+pub struct Args {
+    /// Radar site ICAO identifier, e.g. KDMX
+    #[arg(long, default_value = "KDMX", value_parser = parse_site)]
+    pub site: String,
+
+    /// Target scan date (UTC) as YYYY-MM-DD
+    #[arg(long, default_value = "2022-03-05", value_parser = parse_date)]
+    pub date: NaiveDate,
+
+    /// Target scan time (UTC) as HH:MM or HH:MM:SS
+    #[arg(long, default_value = "23:30:00", value_parser = parse_time)]
+    pub time: NaiveTime,
+
+    /// How the scan is chosen relative to the target time
+    #[arg(long, value_enum, default_value_t = ScanSelection::Nearest)]
+    pub scan_selection: ScanSelection,
+
+    /// Maximum minutes between the target time and the selected scan
+    #[arg(long, value_name = "MINUTES")]
+    pub scan_tolerance: Option<u32>,
+
+    /// Which sweeps are kept where the volume repeats an elevation angle (split cuts, SAILS)
+    #[arg(long, value_enum, default_value_t = SweepSelection::ByMoment)]
+    pub sweep_selection: SweepSelection,
+
+    /// Radar moment to render
+    #[arg(long, value_enum, default_value_t = Moment::Reflectivity)]
+    pub moment: Moment,
+
+    /// Gate value threshold in the moment's units, defaults per moment (e.g. 0.5 dBZ)
+    #[arg(long, allow_negative_numbers = true, value_parser = parse_value)]
+    pub threshold: Option<f32>,
+
+    /// Effective earth radius multiplier modeling beam refraction
+    #[arg(long, default_value_t = STANDARD_REFRACTION_FACTOR, value_parser = parse_positive)]
+    pub refraction_factor: f32,
+
+    /// How the volume's gates are sampled
+    #[arg(long, value_enum, default_value_t = SamplingMode::Step)]
+    pub sampling_mode: SamplingMode,
+
+    /// Keep every Nth gate in step sampling
+    #[arg(long, default_value_t = 10000, value_parser = parse_sampling)]
+    pub sampling: u16,
+
+    /// Maximum points kept by budget and weighted sampling
+    #[arg(long, default_value_t = 100000, value_parser = parse_budget)]
+    pub point_budget: u32,
+
+    /// Voxel edge length in meters for voxel-grid sampling
+    #[arg(long, value_name = "METERS", default_value_t = 1000.0, value_parser = parse_positive)]
+    pub voxel_size: f32,
+
+    /// Gates at or above this value (in the moment's units) are always kept by weighted sampling
+    #[arg(long, default_value_t = 40.0, allow_negative_numbers = true, value_parser = parse_value)]
+    pub keep_above: f32,
+
+    /// Clustering algorithm applied to the sampled points
+    #[arg(long, value_enum, default_value_t = ClusteringMode::DBSCAN)]
+    pub clustering_mode: ClusteringMode,
+
+    /// Clustering neighborhood radius in kilometers, must be positive
+    #[arg(long, default_value_t = 10.0, allow_negative_numbers = true, value_parser = parse_positive)]
+    pub clustering_threshold: f32,
+
+    /// Radius in meters within which neighboring points are counted for the density color modes
+    #[arg(long, value_name = "METERS", default_value_t = 5000.0, value_parser = parse_positive)]
+    pub density_radius: f32,
+
+    /// Colormap for the density color mode
+    #[arg(long, value_enum, default_value_t = DensityColormap::Viridis)]
+    pub density_colormap: DensityColormap,
+
+    /// Interpolate the volume onto a Cartesian grid with this weighting
+    #[arg(long, value_enum)]
+    pub grid: Option<GridInterpolation>,
+
+    /// Grid reach from the radar in each horizontal direction, in kilometers
+    #[arg(long, value_name = "KM", default_value_t = 150.0, value_parser = parse_positive)]
+    pub grid_extent: f32,
+
+    /// Grid top above the surface, in kilometers
+    #[arg(long, value_name = "KM", default_value_t = 15.0, value_parser = parse_positive)]
+    pub grid_top: f32,
+
+    /// Grid horizontal cell size in meters
+    #[arg(long, value_name = "METERS", default_value_t = 2000.0, value_parser = parse_positive)]
+    pub grid_resolution: f32,
+
+    /// Grid vertical cell size in meters
+    #[arg(long, value_name = "METERS", default_value_t = 1000.0, value_parser = parse_positive)]
+    pub grid_vertical_resolution: f32,
+
+    /// Initial point color mode
+    #[arg(long, value_enum, default_value_t = PointColorMode::Raw)]
+    pub color_mode: PointColorMode,
+
+    /// Initial gate render mode
+    #[arg(long, value_enum, default_value_t = GateRenderMode::Points)]
+    pub render_mode: GateRenderMode,
+
+    /// Hide range-folded gates, which are otherwise drawn in purple
+    #[arg(long)]
+    pub hide_range_folded: bool,
+
+    /// Draw the gridded moment as a surface at this value, may be repeated
+    #[arg(
+        long = "isosurface",
+        value_name = "VALUE",
+        default_values_t = [30.0, 45.0, 60.0],
+        allow_negative_numbers = true,
+        value_parser = parse_value
+    )]
+    pub isosurface_levels: Vec<f32>,
+
+    /// Ray-march the grid as a volume instead of drawing the gates
+    #[arg(long, requires = "grid")]
+    pub direct_volume: bool,
+
+    /// Distance in meters between samples along each ray when ray-marching the grid
+    #[arg(long, value_name = "METERS", default_value_t = 250.0, value_parser = parse_positive)]
+    pub volume_step: f32,
+
+    /// Multiplier applied to the transfer function's opacities when ray-marching the grid
+    #[arg(long, default_value_t = 1.0, value_parser = parse_positive)]
+    pub volume_opacity: f32,
+
+    /// GRLevelX .pal color table to use for its product's moment instead of the built-in table, may
+    /// be repeated. Tables can also be loaded and switched in the GUI.
+    #[arg(long = "color-table", value_name = "PATH", value_parser = parse_color_table)]
+    pub color_tables: Vec<ColorTable>,
+
+    /// Initial transfer function preset for ray-marching the grid
+    #[arg(long, value_enum, default_value_t = TransferPreset::ColorTable)]
+    pub transfer_preset: TransferPreset,
+
+    /// Transfer function file to start with instead of the preset, as saved from the GUI for the
+    /// same moment
+    #[arg(long, value_name = "PATH", value_parser = parse_file)]
+    pub transfer_function: Option<PathBuf>,
+
+    /// Initial camera interaction mode
+    #[arg(long, value_enum, default_value_t = InteractionMode::ManualOrbit)]
+    pub interaction_mode: InteractionMode,
+
+    /// Local Archive II file to render instead of downloading, may be repeated. The first file is
+    /// loaded initially and the others can be selected in the GUI.
+    #[arg(long = "file", value_name = "PATH", value_parser = parse_file)]
+    pub files: Vec<PathBuf>,
+
+    /// Directory downloaded volumes are cached in
+    #[arg(long, value_name = "DIR", default_value = "cache")]
+    pub cache_dir: PathBuf,
+
+    /// Maximum total size of cached volumes in megabytes, least-recently-used volumes are evicted
+    #[arg(
+        long,
+        value_name = "MB",
+        default_value_t = 2048,
+        value_parser = clap::value_parser!(u64).range(..=MAX_CACHE_SIZE_MB)
+    )]
+    pub cache_size_mb: u64,
+
+    /// Print the cached volumes and exit
+    #[arg(long)]
+    pub list_cache: bool,
+
+    /// Delete all cached volumes and exit
+    #[arg(long)]
+    pub clear_cache: bool,
+
+    /// Load the initial volume, time point generation over this many iterations against the serial
+    /// baseline, and exit
+    #[arg(long, value_name = "ITERATIONS", value_parser = clap::value_parser!(u32).range(1..))]
+    pub benchmark_pointing: Option<u32>,
+}
+
+impl Args {
+    /// Checks constraints spanning several arguments, which their parsers can't.
+    pub fn check(&self) -> Result<(), String> {
+        match self.data_params().grid {
+            Some(grid) => grid.check_size(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn cache_max_bytes(&self) -> u64 {
+        self.cache_size_mb * BYTES_PER_MB
+    }
+
+    pub fn data_params(&self) -> DataParams {
+        DataParams {
+            site: self.site.clone(),
+            date: self.date,
+            time: self.time,
+            scan_selection: self.scan_selection,
+            scan_tolerance: self.scan_tolerance,
+            sweep_selection: self.sweep_selection,
+            moment: self.moment,
+            threshold: self
+                .threshold
+                .unwrap_or_else(|| self.moment.default_threshold()),
+            refraction_factor: self.refraction_factor,
+            sampling_mode: self.sampling_mode,
+            sampling: self.sampling,
+            point_budget: self.point_budget,
+            voxel_size_m: self.voxel_size,
+            keep_above: self.keep_above,
+            clustering_mode: self.clustering_mode,
+            clustering_threshold: self.clustering_threshold,
+            density_radius_m: self.density_radius,
+            grid: self.grid.map(|interpolation| GridParams {
+                interpolation,
+                extent_km: self.grid_extent,
+                top_km: self.grid_top,
+                horizontal_resolution_m: self.grid_resolution,
+                vertical_resolution_m: self.grid_vertical_resolution,
+            }),
+            source: match self.files.first() {
+                Some(path) => DataSource::Local(path.clone()),
+                None => DataSource::Remote,
+            },
+        }
+    }
+
+    /// The initial visualization parameters, failing if the transfer function file doesn't load for
+    /// the moment.
+    pub fn vis_params(&self) -> Result<VisParams, String> {
+        let color_table = select_color_table(self.moment, &self.color_tables);
+        let transfer_function = match self.transfer_function {
+            Some(ref path) => TransferFunction::load(path, self.moment)
+                .map_err(|err| format!("\"{}\": {}", path.display(), err))?,
+            None => TransferFunction::preset(self.transfer_preset, self.moment, &color_table),
+        };
+
+        Ok(VisParams {
+            interaction_mode: self.interaction_mode,
+            point_color_mode: self.color_mode,
+            gate_render_mode: self.render_mode,
+            show_range_folded: !self.hide_range_folded,
+            density_colormap: self.density_colormap,
+            color_table,
+            isosurface_levels: {
+                let mut levels = self.isosurface_levels.clone();
+                levels.sort_by(f32::total_cmp);
+                levels.dedup();
+                levels
+            },
+            direct_volume: self.direct_volume,
+            volume_step_m: self.volume_step,
+            volume_opacity_scale: self.volume_opacity,
+            transfer_function,
+            transfer_preset: self.transfer_preset,
+        })
+    }
+}
+
+/// Prints a summary of each cached volume.
+pub fn print_cache(cache: &Cache) {
+    println!(
+        "Cache {}: {} volumes, {:.1} of {:.1} MB",
+        cache.dir().display(),
+        cache.entries().len(),
+        cache.total_bytes() as f64 / (1024.0 * 1024.0),
+        cache.max_bytes() as f64 / (1024.0 * 1024.0),
+    );
+
+    for entry in cache.entries() {
+        println!(
+            "  {}  {}  {}  {:.1} MB",
+            entry.site,
+            entry.date,
+            entry.identifier,
+            entry.size as f64 / (1024.0 * 1024.0),
+        );
+    }
+}
+
+pub fn parse_site(value: &str) -> Result<String, String> {
+    let site = value.trim().to_uppercase();
+    if site.len() != 4 || !site.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(format!(
+            "\"{}\" is not a 4-character ICAO site identifier (e.g. KDMX)",
+            value
+        ));
+    }
+
+    Ok(site)
+}
+
+pub fn parse_file(value: &str) -> Result<PathBuf, String> {
+    let path = PathBuf::from(value);
+    if !path.is_file() || File::open(&path).is_err() {
+        return Err(format!("\"{}\" is not a readable file", value));
+    }
+
+    Ok(path)
+}
+
+fn parse_color_table(value: &str) -> Result<ColorTable, String> {
+    ColorTable::load(Path::new(value)).map_err(|err| format!("\"{}\": {}", value, err))
+}
+
+pub fn parse_date(value: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|err| format!("\"{}\" is not a YYYY-MM-DD date: {}", value, err))
+}
+
+pub fn parse_time(value: &str) -> Result<NaiveTime, String> {
+    NaiveTime::parse_from_str(value, "%H:%M:%S")
+        .or_else(|_| NaiveTime::parse_from_str(value, "%H:%M"))
+        .map_err(|err| format!("\"{}\" is not an HH:MM[:SS] time: {}", value, err))
+}
+
+pub fn parse_sampling(value: &str) -> Result<u16, String> {
+    match value.trim().parse::<u16>() {
+        Ok(sampling) if sampling >= 1 => Ok(sampling),
+        _ => Err(format!("\"{}\" is not a sampling step of 1-65535", value)),
+    }
+}
+
+pub fn parse_budget(value: &str) -> Result<u32, String> {
+    match value.trim().parse::<u32>() {
+        Ok(budget) if budget >= 1 => Ok(budget),
+        _ => Err(format!("\"{}\" is not a positive point budget", value)),
+    }
+}
+
+pub fn parse_tolerance(value: &str) -> Result<Option<u32>, String> {
+    match value.trim() {
+        "" => Ok(None),
+        minutes => minutes
+            .parse::<u32>()
+            .map(Some)
+            .map_err(|_| format!("\"{}\" is not a whole number of minutes", value)),
+    }
+}
+
+pub fn parse_value(value: &str) -> Result<f32, String> {
+    match value.trim().parse::<f32>() {
+        Ok(parsed) if parsed.is_finite() => Ok(parsed),
+        _ => Err(format!("\"{}\" is not a number", value)),
+    }
+}
+
+pub fn parse_positive(value: &str) -> Result<f32, String> {
+    let parsed = value
+        .trim()
+        .parse::<f32>()
+        .map_err(|err| format!("\"{}\" is not a number: {}", value, err))?;
+
+    if !parsed.is_finite() || parsed <= 0.0 {
+        return Err(format!("value must be positive, got {}", parsed));
+    }
+
+    Ok(parsed)
+}
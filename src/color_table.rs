@@ -0,0 +1,414 @@
+use crate::data::RgbColor;
+use crate::param::Moment;
+use crate::result::{Error, Result};
+use std::fs;
+use std::path::Path;
+
+pub type RgbaColor = (u8, u8, u8, u8);
+
+/// The purple operational displays use for range-folded ("RF") gates, for tables without an RF
+/// entry.
+const DEFAULT_RANGE_FOLDED_COLOR: RgbColor = (0x77, 0x00, 0x7d);
+
+/// A color table entry, starting at its value and running up to the next entry's.
+#[derive(PartialEq, Copy, Clone, Debug)]
+struct ColorEntry {
+    /// Value in the table's units.
+    value: f32,
+    color: RgbaColor,
+    gradient: Gradient,
+}
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+enum Gradient {
+    /// The entry's color throughout (SolidColor lines).
+    Solid,
+    /// Blends to the next entry's color (Color lines with one color).
+    ToNext,
+    /// Blends to its own end color (Color lines with two colors).
+    To(RgbaColor),
+}
+
+/// A color table in the GRLevelX/GR2Analyst palette (.pal) format. Supports Color, Color4,
+/// SolidColor, and SolidColor4 entries, the Product, Units, Scale, and Offset settings, and the RF
+/// range-folded color; other settings are ignored. Values below the first entry have no color.
+/// Tables in knots, miles per hour, or kilometers per hour without a Scale are scaled from m/s.
+#[derive(PartialEq, Clone, Debug)]
+pub struct ColorTable {
+    /// File name, or "Default" for the built-in tables.
+    pub name: String,
+    pub product: Option<String>,
+    pub units: Option<String>,
+    /// Table values are moment values times the scale plus the offset, e.g. to give velocity
+    /// tables in knots.
+    scale: f32,
+    offset: f32,
+    entries: Vec<ColorEntry>,
+    range_folded: Option<RgbaColor>,
+}
+
+impl ColorTable {
+    /// The table shipped for the moment.
+    pub fn builtin(moment: Moment) -> Self {
+        let text = match moment {
+            Moment::Reflectivity => include_str!("../palettes/REF.pal"),
+            Moment::Velocity => include_str!("../palettes/VEL.pal"),
+            Moment::SpectrumWidth => include_str!("../palettes/SW.pal"),
+            Moment::DifferentialReflectivity => include_str!("../palettes/ZDR.pal"),
+            Moment::CorrelationCoefficient => include_str!("../palettes/RHO.pal"),
+            Moment::DifferentialPhase => include_str!("../palettes/PHI.pal"),
+            Moment::SpecificDifferentialPhase => include_str!("../palettes/KDP.pal"),
+        };
+
+        Self::parse("Default", text).expect("built-in color tables are valid")
+    }
+
+    /// Reads a .pal file, named after the file.
+    pub fn load(path: &Path) -> Result<Self> {
+        let name = path.file_stem().map_or(path.display().to_string(), |stem| {
+            stem.to_string_lossy().to_string()
+        });
+
+        Self::parse(&name, &fs::read_to_string(path)?)
+    }
+
+    pub fn parse(name: &str, text: &str) -> Result<Self> {
+        let mut table = ColorTable {
+            name: name.to_string(),
+            product: None,
+            units: None,
+            scale: 1.0,
+            offset: 0.0,
+            entries: Vec::new(),
+            range_folded: None,
+        };
+
+        let mut scale = None;
+        for (line_index, line) in text.lines().enumerate() {
+            // Everything after a semicolon is a comment
+            let line = line.split(';').next().unwrap_or_default().trim();
+            let Some((key, rest)) = line.split_once(':') else {
+                continue;
+            };
+
+            let invalid =
+                || Error::ColorTableError(format!("line {} of {}: {}", line_index + 1, name, line));
+            let rest = rest.trim();
+
+            match key.trim().to_lowercase().as_str() {
+                "product" => table.product = Some(rest.to_string()),
+                "units" => table.units = Some(rest.to_string()),
+                "scale" => scale = Some(parse_number(rest).ok_or_else(invalid)?),
+                "offset" => table.offset = parse_number(rest).ok_or_else(invalid)?,
+                "rf" => {
+                    let numbers = parse_numbers(rest).ok_or_else(invalid)?;
+                    table.range_folded = Some(match numbers.as_slice() {
+                        [red, green, blue] => rgba([*red, *green, *blue, 255.0]),
+                        [red, green, blue, alpha] => rgba([*red, *green, *blue, *alpha]),
+                        _ => return Err(invalid()),
+                    });
+                }
+                "color" | "color4" | "solidcolor" | "solidcolor4" => {
+                    let numbers = parse_numbers(rest).ok_or_else(invalid)?;
+                    table
+                        .entries
+                        .push(parse_entry(key.trim(), &numbers).ok_or_else(invalid)?);
+                }
+                _ => {}
+            }
+        }
+
+        // An explicit scale already converts from m/s, but otherwise the units have to
+        table.scale = scale
+            .or_else(|| table.units.as_deref().and_then(speed_unit_scale))
+            .unwrap_or(1.0);
+
+        if table.scale == 0.0 {
+            return Err(Error::ColorTableError(format!("{} has a zero scale", name)));
+        }
+        if table.entries.is_empty() {
+            return Err(Error::ColorTableError(format!("{} has no colors", name)));
+        }
+
+        table.entries.sort_by(|a, b| a.value.total_cmp(&b.value));
+        Ok(table)
+    }
+
+    /// The moment the table's product is for, if it names one.
+    pub fn moment(&self) -> Option<Moment> {
+        self.product.as_deref().and_then(Moment::from_product)
+    }
+
+    /// The color for a value in the moment's units, or None if it is below the table.
+    pub fn color(&self, value: f32) -> Option<RgbaColor> {
+        let value = value * self.scale + self.offset;
+
+        let next = self.entries.partition_point(|entry| entry.value <= value);
+        let entry = self.entries.get(next.checked_sub(1)?)?;
+
+        let (end_color, end_value) = match (entry.gradient, self.entries.get(next)) {
+            (Gradient::Solid, _) | (_, None) => return Some(entry.color),
+            (Gradient::ToNext, Some(next)) => (next.color, next.value),
+            (Gradient::To(end_color), Some(next)) => (end_color, next.value),
+        };
+
+        let fraction = (value - entry.value) / (end_value - entry.value);
+        let mix =
+            |from: u8, to: u8| (from as f32 + (to as f32 - from as f32) * fraction).round() as u8;
+
+        Some((
+            mix(entry.color.0, end_color.0),
+            mix(entry.color.1, end_color.1),
+            mix(entry.color.2, end_color.2),
+            mix(entry.color.3, end_color.3),
+        ))
+    }
+
+    /// The color for a value without its alpha, black if it is below the table.
+    pub fn rgb(&self, value: f32) -> RgbColor {
+        self.color(value)
+            .map_or((0, 0, 0), |(red, green, blue, _)| (red, green, blue))
+    }
+
+    pub fn range_folded_color(&self) -> RgbColor {
+        self.range_folded
+            .map_or(DEFAULT_RANGE_FOLDED_COLOR, |(red, green, blue, _)| {
+                (red, green, blue)
+            })
+    }
+
+    /// The entries' starting values in the moment's units, where the colors change, in ascending
+    /// order.
+    pub fn levels(&self) -> Vec<f32> {
+        let mut levels = self
+            .entries
+            .iter()
+            .map(|entry| (entry.value - self.offset) / self.scale)
+            .collect::<Vec<_>>();
+        levels.sort_by(f32::total_cmp);
+        levels
+    }
+}
+
+/// The first of the loaded tables made for the moment, or its built-in table if there are none.
+pub fn select_color_table(moment: Moment, loaded: &[ColorTable]) -> ColorTable {
+    loaded
+        .iter()
+        .find(|table| table.moment() == Some(moment))
+        .cloned()
+        .unwrap_or_else(|| ColorTable::builtin(moment))
+}
+
+/// Parses an entry's numbers per its kind: the value, a color, and for blended entries an optional
+/// end color, with alpha for the "4" kinds.
+fn parse_entry(key: &str, numbers: &[f32]) -> Option<ColorEntry> {
+    let key = key.to_lowercase();
+    let channels = if key.ends_with('4') { 4 } else { 3 };
+    let solid = key.starts_with("solid");
+
+    let (value, colors) = numbers.split_first()?;
+    let color = |channel_values: &[f32]| {
+        let alpha = if channels == 4 {
+            channel_values[3]
+        } else {
+            255.0
+        };
+        rgba([
+            channel_values[0],
+            channel_values[1],
+            channel_values[2],
+            alpha,
+        ])
+    };
+
+    let gradient = match (solid, colors.len() / channels, colors.len() % channels) {
+        (_, _, remainder) if remainder != 0 => return None,
+        (true, 1, _) => Gradient::Solid,
+        (false, 1, _) => Gradient::ToNext,
+        (false, 2, _) => Gradient::To(color(&colors[channels..])),
+        _ => return None,
+    };
+
+    Some(ColorEntry {
+        value: *value,
+        color: color(colors),
+        gradient,
+    })
+}
+
+/// The scale from m/s to a speed unit, for the units velocity tables are commonly written in.
+fn speed_unit_scale(units: &str) -> Option<f32> {
+    match units.trim().to_uppercase().as_str() {
+        "KTS" | "KT" | "KNOTS" => Some(1.943844),
+        "MPH" => Some(2.236936),
+        "KPH" | "KMH" | "KM/H" => Some(3.6),
+        _ => None,
+    }
+}
+
+fn rgba(channels: [f32; 4]) -> RgbaColor {
+    let channel = |value: f32| value.clamp(0.0, 255.0).round() as u8;
+    (
+        channel(channels[0]),
+        channel(channels[1]),
+        channel(channels[2]),
+        channel(channels[3]),
+    )
+}
+
+fn parse_number(text: &str) -> Option<f32> {
+    text.parse::<f32>().ok().filter(|number| number.is_finite())
+}
+
+fn parse_numbers(text: &str) -> Option<Vec<f32>> {
+    text.split_whitespace().map(parse_number).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> ColorTable {
+        ColorTable::parse("test", text).unwrap()
+    }
+
+    #[test]
+    fn builtin_tables_name_their_moments() {
+        for moment in Moment::ALL {
+            let table = ColorTable::builtin(moment);
+            assert_eq!(table.moment(), Some(moment));
+            assert!(!table.levels().is_empty());
+        }
+    }
+
+    #[test]
+    fn color_with_one_color_blends_to_the_next_entry() {
+        let table = parse("Color: 10 0 0 0\nColor: 20 200 100 50\n");
+
+        assert_eq!(table.color(9.9), None);
+        assert_eq!(table.rgb(9.9), (0, 0, 0));
+        assert_eq!(table.color(10.0), Some((0, 0, 0, 255)));
+        assert_eq!(table.color(15.0), Some((100, 50, 25, 255)));
+        // The last entry has nothing to blend to
+        assert_eq!(table.color(30.0), Some((200, 100, 50, 255)));
+    }
+
+    #[test]
+    fn color_with_two_colors_blends_to_its_end_color() {
+        let table = parse("Color: 0 0 0 0 100 100 100\nColor: 10 255 0 0\n");
+
+        assert_eq!(table.color(5.0), Some((50, 50, 50, 255)));
+        assert_eq!(table.color(10.0), Some((255, 0, 0, 255)));
+    }
+
+    #[test]
+    fn solid_colors_do_not_blend() {
+        let table = parse("SolidColor: 0 10 20 30\nSolidColor4: 5 40 50 60 128\nColor: 10 0 0 0\n");
+
+        assert_eq!(table.color(4.9), Some((10, 20, 30, 255)));
+        assert_eq!(table.color(7.5), Some((40, 50, 60, 128)));
+    }
+
+    #[test]
+    fn color4_blends_alpha() {
+        let table = parse("Color4: 0 0 0 0 0 100 100 100 200\nColor4: 10 0 0 0 0\n");
+
+        assert_eq!(table.color(5.0), Some((50, 50, 50, 100)));
+    }
+
+    #[test]
+    fn range_folded_color() {
+        assert_eq!(
+            parse("Color: 0 0 0 0\n").range_folded_color(),
+            (0x77, 0x00, 0x7d)
+        );
+        assert_eq!(
+            parse("RF: 1 2 3\nColor: 0 0 0 0\n").range_folded_color(),
+            (1, 2, 3)
+        );
+        assert_eq!(
+            parse("RF: 1 2 3 4\nColor: 0 0 0 0\n").range_folded_color(),
+            (1, 2, 3)
+        );
+    }
+
+    #[test]
+    fn scale_and_offset_convert_moment_values() {
+        let table = parse("Scale: 2\nOffset: 10\nSolidColor: 20 1 1 1\nSolidColor: 30 2 2 2\n");
+
+        // Table value 20 is moment value 5, and 30 is 10
+        assert_eq!(table.rgb(4.9), (0, 0, 0));
+        assert_eq!(table.rgb(5.0), (1, 1, 1));
+        assert_eq!(table.rgb(10.0), (2, 2, 2));
+        assert_eq!(table.levels(), vec![5.0, 10.0]);
+    }
+
+    #[test]
+    fn speed_units_without_a_scale_are_converted() {
+        let table = parse("Product: BV\nUnits: KTS\nSolidColor: 10 1 1 1\n");
+        assert_eq!(table.moment(), Some(Moment::Velocity));
+        assert_eq!(table.rgb(5.0), (0, 0, 0));
+        // 5.2 m/s is about 10.1 knots
+        assert_eq!(table.rgb(5.2), (1, 1, 1));
+
+        let table = parse("Units: MPH\nSolidColor: 10 1 1 1\n");
+        assert_eq!(table.rgb(4.4), (0, 0, 0));
+        assert_eq!(table.rgb(4.5), (1, 1, 1));
+
+        // An explicit scale is used as is
+        let table = parse("Units: KTS\nScale: 1\nSolidColor: 10 1 1 1\n");
+        assert_eq!(table.rgb(10.0), (1, 1, 1));
+
+        // Other units are left alone
+        let table = parse("Units: dBZ\nSolidColor: 10 1 1 1\n");
+        assert_eq!(table.rgb(10.0), (1, 1, 1));
+    }
+
+    #[test]
+    fn comments_and_unknown_settings_are_ignored() {
+        let table = parse(
+            "; A comment\n\
+             Product: BR ; reflectivity\n\
+             Step: 5\n\
+             \n\
+             Color: 0 1 2 3 ; Color: 99 9 9 9\n",
+        );
+
+        assert_eq!(table.product.as_deref(), Some("BR"));
+        assert_eq!(table.levels(), vec![0.0]);
+        assert_eq!(table.color(0.0), Some((1, 2, 3, 255)));
+    }
+
+    #[test]
+    fn entries_are_sorted() {
+        let table = parse("SolidColor: 10 2 2 2\nSolidColor: 0 1 1 1\n");
+
+        assert_eq!(table.levels(), vec![0.0, 10.0]);
+        assert_eq!(table.rgb(5.0), (1, 1, 1));
+    }
+
+    #[test]
+    fn bad_lines_are_errors() {
+        for text in [
+            "Color: 0 1 2\n",
+            "Color: 0 1 2 3 4\n",
+            "Color: 0 1 2 3 4 5 6 7 8 9\n",
+            "Color: zero 1 2 3\n",
+            "SolidColor: 0 1 2 3 4 5 6\n",
+            "Color4: 0 1 2 3\n",
+            "RF: 1 2\nColor: 0 1 2 3\n",
+            "Scale: x\nColor: 0 1 2 3\n",
+            "Scale: 0\nColor: 0 1 2 3\n",
+            "Product: BR\n",
+        ] {
+            assert!(
+                matches!(
+                    ColorTable::parse("test", text),
+                    Err(Error::ColorTableError(_))
+                ),
+                "{:?} parsed",
+                text
+            );
+        }
+    }
+}
@@ -1,80 +1,189 @@
-use crate::result::Result;
-use crate::state::ProcessingStatistics;
-use crate::RENDER_RATIO_TO_M;
-use chrono::{NaiveDate, NaiveTime};
+use crate::beam::{beam_height_m, ground_range_m, render_position};
+use crate::cache::Cache;
+use crate::param::{DataParams, DataSource, Moment, ScanSelection, SweepSelection};
+use crate::result::{Error, Result};
+use crate::state::{check_cancelled, ProcessingStage, ProcessingStatistics};
+use chrono::{Duration, NaiveDate, NaiveDateTime, NaiveTime};
 use nexrad::decode::decode_file;
 use nexrad::decompress::decompress_file;
 use nexrad::download::{download_file, list_files};
-use nexrad::file::FileMetadata;
-use nexrad::model::DataFile;
+use nexrad::file::{is_compressed, FileMetadata};
+use nexrad::model::{DataFile, DataMoment, Message31};
+use rayon::prelude::*;
 use std::f32::consts::PI;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Mutex;
 use std::time::Instant;
 use three_d::Vector3;
+use tokio_util::sync::CancellationToken;
 
 pub const BELOW_THRESHOLD: f32 = 999.0;
 pub const MOMENT_FOLDED: f32 = 998.0;
 
-type RgbColor = (u8, u8, u8);
+pub type RgbColor = (u8, u8, u8);
+
+/// Nominal WSR-88D half-power beam width.
+pub const BEAM_WIDTH_DEG: f32 = 0.95;
+
+/// The sample volume a gate's value represents, centered on the radial and gate.
+#[derive(Clone, Copy, Debug)]
+pub struct GateGeometry {
+    /// Radial center in render angle, radians counterclockwise from +x.
+    pub azimuth_rad: f32,
+    pub elevation_rad: f32,
+    /// Slant range to the center of the gate.
+    pub range_m: f32,
+    /// Angular spacing between radials, e.g. 0.5° for super-resolution sweeps.
+    pub azimuth_width_rad: f32,
+    pub beam_width_rad: f32,
+    pub gate_length_m: f32,
+}
 
 #[derive(Clone)]
 pub struct ColoredPoint {
     pub pos: Vector3<f32>,
-    pub strength: f32,
+    pub gate: GateGeometry,
+    /// The gate's scaled value in its moment's units, NaN for range-folded gates.
+    pub value: f32,
+    /// Whether the gate's value was range folded (ambiguous), in which case it has no value and is
+    /// excluded from statistics and clustering.
+    pub folded: bool,
     pub raw: RgbColor,
+    /// Neighbor count within the density radius, log-normalized from 0 to 1 against the densest
+    /// point.
+    pub density_level: f32,
     pub density: RgbColor,
     pub hybrid: RgbColor,
+    pub cluster: RgbColor,
 }
 
 impl ColoredPoint {
-    fn new(pos: Vector3<f32>, strength: f32) -> Self {
+    pub fn new(pos: Vector3<f32>, gate: GateGeometry, value: f32) -> Self {
         Self {
             pos,
-            strength,
+            gate,
+            value,
+            folded: false,
             raw: (0, 0, 0),
+            density_level: 0.0,
             density: (0, 0, 0),
             hybrid: (0, 0, 0),
+            cluster: (0, 0, 0),
+        }
+    }
+
+    pub fn new_folded(pos: Vector3<f32>, gate: GateGeometry) -> Self {
+        Self {
+            folded: true,
+            ..Self::new(pos, gate, f32::NAN)
         }
     }
 }
 
 pub async fn get_data(
-    site: &str,
-    date: &NaiveDate,
-    time: &NaiveTime,
+    data_params: &DataParams,
+    cache: &Mutex<Cache>,
+    cancel: &CancellationToken,
+    stage: &mut ProcessingStage,
     stats: &mut ProcessingStatistics,
 ) -> Result<DataFile> {
+    *stage = ProcessingStage::Load;
     let load_start = Instant::now();
 
-    let files = list_files(site, date).await?;
-    if files.is_empty() {
-        panic!("No files found for date/site");
-    }
+    match data_params.source {
+        DataSource::Remote => {
+            let target = data_params.date.and_time(data_params.time);
+            let files = list_candidate_files(
+                &data_params.site,
+                &target,
+                data_params.scan_selection,
+                cancel,
+            )
+            .await?;
+
+            let file = nearest_file(
+                &files,
+                &target,
+                data_params.scan_selection,
+                data_params.scan_tolerance,
+            )?;
+            println!("Selected file: {}", file.identifier());
+
+            let cached_data = cache.lock().unwrap().get(file)?;
+            let from_cache = cached_data.is_some();
+            let data = match cached_data {
+                Some(data) => {
+                    println!("File found in cache, skipping download.");
+                    data
+                }
+                None => download_to_cache(file, cache, cancel).await?,
+            };
 
-    let file = nearest_file(&files, time);
-    println!("Nearest file: {}", file.identifier());
+            stats.load_ms = load_start.elapsed().as_millis();
 
-    let data = if !std::path::Path::new(&file.identifier()).exists() {
-        println!("Downloading file...");
-        let downloaded_data = download_file(file).await?;
+            match decode_data(&data, cancel, stage, stats) {
+                Err(err) if from_cache => {
+                    println!("Cached file failed to decode ({}), re-downloading...", err);
+                    *stage = ProcessingStage::Load;
+                    cache.lock().unwrap().remove(file.identifier())?;
 
-        println!("Writing file to disk...");
-        std::fs::write(&file.identifier(), &downloaded_data)?;
+                    let data = download_to_cache(file, cache, cancel).await?;
+                    stats.load_ms = load_start.elapsed().as_millis();
 
-        downloaded_data
-    } else {
-        println!("File already exists on disk, skipping download.");
-        std::fs::read(&file.identifier())?
-    };
+                    decode_data(&data, cancel, stage, stats)
+                }
+                result => result,
+            }
+        }
+        DataSource::Local(ref path) => {
+            println!("Reading local file {}...", path.display());
+            let data = std::fs::read(path)?;
+            stats.load_ms = load_start.elapsed().as_millis();
 
-    stats.load_ms = load_start.elapsed().as_millis();
+            decode_data(&data, cancel, stage, stats)
+        }
+    }
+}
 
+async fn download_to_cache(
+    file: &FileMetadata,
+    cache: &Mutex<Cache>,
+    cancel: &CancellationToken,
+) -> Result<Vec<u8>> {
+    println!("Downloading file...");
+    let data = cancellable(download_file(file), cancel).await?;
+
+    println!("Writing file to cache...");
+    cache.lock().unwrap().insert(file, &data)?;
+
+    Ok(data)
+}
+
+/// Decompresses (if needed) and decodes an Archive II file's contents. The decoder panics on some
+/// malformed input, so panics are caught and returned as decode errors.
+fn decode_data(
+    data: &[u8],
+    cancel: &CancellationToken,
+    stage: &mut ProcessingStage,
+    stats: &mut ProcessingStatistics,
+) -> Result<DataFile> {
+    check_cancelled(cancel)?;
+    *stage = ProcessingStage::Decompress;
     let decompress_start = Instant::now();
-    let decompressed_data = decompress_file(&data)?;
+    let decompressed_data = if is_compressed(data) {
+        catch_decode_panic(|| Ok(decompress_file(data)?))?
+    } else {
+        println!("File is not compressed, skipping decompression.");
+        data.to_vec()
+    };
     stats.decompress_ms = decompress_start.elapsed().as_millis();
     println!("Decompressed file has {} bytes.", decompressed_data.len());
 
+    check_cancelled(cancel)?;
+    *stage = ProcessingStage::Decode;
     let decode_start = Instant::now();
-    let decoded = decode_file(&decompressed_data)?;
+    let decoded = catch_decode_panic(|| Ok(decode_file(&decompressed_data)?))?;
     stats.decode_ms = decode_start.elapsed().as_millis();
     println!(
         "Decoded file has {} elevation scans.",
@@ -84,95 +193,706 @@ pub async fn get_data(
     Ok(decoded)
 }
 
-pub fn nearest_file<'a>(files: &'a Vec<FileMetadata>, time: &NaiveTime) -> &'a FileMetadata {
-    let mut nearest = files.first().unwrap();
+/// Awaits a network request unless the job is cancelled first, in which case it's dropped.
+async fn cancellable<T>(
+    request: impl Future<Output = nexrad::result::Result<T>>,
+    cancel: &CancellationToken,
+) -> Result<T> {
+    tokio::select! {
+        result = request => Ok(result?),
+        _ = cancel.cancelled() => Err(Error::CancelledError),
+    }
+}
 
-    let get_file_time = |file: &FileMetadata| {
-        let identifier_parts = file.identifier().split('_').collect::<Vec<&str>>();
-        let identifier_time = identifier_parts[1];
-        NaiveTime::parse_from_str(identifier_time, "%H%M%S").unwrap()
-    };
+fn catch_decode_panic<T>(decode: impl FnOnce() -> Result<T>) -> Result<T> {
+    panic::catch_unwind(AssertUnwindSafe(decode)).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<String>()
+            .cloned()
+            .or_else(|| payload.downcast_ref::<&str>().map(|s| s.to_string()))
+            .unwrap_or_else(|| "unknown panic".to_string());
+
+        Err(Error::DecodeError(message))
+    })
+}
+
+/// Lists the files which could satisfy the selection policy, including the adjacent day's files
+/// when the target's day has no qualifying scan closer than midnight.
+async fn list_candidate_files(
+    site: &str,
+    target: &NaiveDateTime,
+    selection: ScanSelection,
+    cancel: &CancellationToken,
+) -> Result<Vec<FileMetadata>> {
+    let date = target.date();
+    let mut files = cancellable(list_files(site, &date), cancel).await?;
+
+    let best_offset = nearest_file(&files, target, selection, None)
+        .ok()
+        .and_then(file_date_time)
+        .map(|file_time| (file_time - *target).num_seconds().abs());
+
+    let day_start = date.and_hms_opt(0, 0, 0).unwrap();
+    let since_day_start = (*target - day_start).num_seconds();
+    let until_day_end = (day_start + Duration::days(1) - *target).num_seconds();
+
+    if selection != ScanSelection::AtOrAfter
+        && best_offset.is_none_or(|offset| offset > since_day_start)
+    {
+        if let Some(previous_date) = date.pred_opt() {
+            files.extend(cancellable(list_files(site, &previous_date), cancel).await?);
+        }
+    }
 
-    let mut nearest_diff = time.signed_duration_since(get_file_time(nearest));
-    for file in files {
-        let diff = time.signed_duration_since(get_file_time(file));
-        if diff < nearest_diff {
-            nearest = file;
-            nearest_diff = diff;
+    if selection != ScanSelection::AtOrBefore
+        && best_offset.is_none_or(|offset| offset > until_day_end)
+    {
+        if let Some(next_date) = date.succ_opt() {
+            files.extend(cancellable(list_files(site, &next_date), cancel).await?);
         }
     }
 
-    nearest
+    Ok(files)
 }
 
-pub fn get_points(data: &DataFile, threshold: f32) -> Vec<ColoredPoint> {
-    let mut points: Vec<ColoredPoint> = Vec::new();
+/// Selects the file best matching the target time per the selection policy and optional
+/// tolerance in minutes. Files whose identifiers don't describe a volume scan are ignored.
+pub fn nearest_file<'a>(
+    files: &'a [FileMetadata],
+    target: &NaiveDateTime,
+    selection: ScanSelection,
+    tolerance: Option<u32>,
+) -> Result<&'a FileMetadata> {
+    files
+        .iter()
+        .filter_map(|file| file_date_time(file).map(|file_time| (file, file_time - *target)))
+        .filter(|(_, offset)| match selection {
+            ScanSelection::Nearest => true,
+            ScanSelection::AtOrBefore => *offset <= Duration::zero(),
+            ScanSelection::AtOrAfter => *offset >= Duration::zero(),
+        })
+        .filter(|(_, offset)| {
+            tolerance.is_none_or(|minutes| offset.num_seconds().abs() <= minutes as i64 * 60)
+        })
+        .min_by_key(|(_, offset)| offset.num_seconds().abs())
+        .map(|(file, _)| file)
+        .ok_or_else(|| {
+            Error::ScanNotFoundError(format!(
+                "{} {}{} (searched {} files)",
+                match selection {
+                    ScanSelection::Nearest => "near",
+                    ScanSelection::AtOrBefore => "at or before",
+                    ScanSelection::AtOrAfter => "at or after",
+                },
+                target,
+                tolerance.map_or(String::new(), |minutes| format!(
+                    " within {} minutes",
+                    minutes
+                )),
+                files.len(),
+            ))
+        })
+}
 
-    for (elevation, radials) in data.elevation_scans() {
-        for radial in radials {
-            let mut azimuth_angle = radial.header().azm() - 90.0;
-            if azimuth_angle < 0.0 {
-                azimuth_angle = 360.0 + azimuth_angle;
-            }
+/// Parses a volume file's scan time from its identifier, e.g. KDMX20220305_233003_V06. Returns
+/// None for files which aren't volume scans, like KDMX20220305_233003_V06_MDM metadata files.
+fn file_date_time(file: &FileMetadata) -> Option<NaiveDateTime> {
+    let identifier_parts = file.identifier().split('_').collect::<Vec<&str>>();
+    if identifier_parts.len() < 2 || identifier_parts.last() == Some(&"MDM") {
+        return None;
+    }
 
-            let azimuth_spacing = radial.header().azm_res() as f32;
+    let identifier_time =
+        NaiveTime::parse_from_str(identifier_parts[1].get(..6)?, "%H%M%S").ok()?;
+    Some(file.date().and_time(identifier_time))
+}
 
-            let mut azimuth = azimuth_angle.floor();
-            if (azimuth_angle + azimuth_spacing).floor() > azimuth {
-                azimuth += azimuth_spacing;
-            }
+/// Sweeps at elevation angles closer than this are considered repeats of the same angle.
+const SWEEP_ANGLE_TOLERANCE_DEG: f32 = 0.2;
 
-            let start_angle = azimuth * (PI / 180.0);
+/// The pulse waveform a sweep was collected with. Archive II files' VCP message isn't decoded, so
+/// this is inferred from the moments present: surveillance (long PRT) cuts carry no velocity.
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+pub enum Waveform {
+    Surveillance,
+    Doppler,
+}
 
-            let data_moment = radial.reflectivity_data().unwrap();
-            let mut distance_m = data_moment.data().data_moment_range_sample_interval() as f32;
+/// A single 360° sweep of the volume, identified by its elevation number since split cuts and
+/// SAILS/MRLE sweeps repeat elevation angles.
+pub struct Sweep<'a> {
+    pub elevation_number: u8,
+    /// Mean elevation angle of the sweep's radials in degrees.
+    pub elevation_angle: f32,
+    pub start_time: NaiveDateTime,
+    pub end_time: NaiveDateTime,
+    pub waveform: Waveform,
+    pub radials: &'a [Message31],
+}
 
-            let mut raw_gates: Vec<u16> =
-                vec![0; data_moment.data().number_data_moment_gates() as usize];
+impl Sweep<'_> {
+    pub fn has_moment(&self, moment: Moment) -> bool {
+        self.radials
+            .iter()
+            .any(|radial| moment_data(radial, moment).is_some())
+    }
+}
 
-            assert_eq!(data_moment.data().data_word_size(), 8);
-            for (i, v) in data_moment.moment_data().iter().enumerate() {
-                raw_gates[i] = *v as u16;
-            }
+/// Lists the volume's sweeps in the order they were collected.
+pub fn sweeps(data: &DataFile) -> Vec<Sweep<'_>> {
+    let mut sweeps = data
+        .elevation_scans()
+        .iter()
+        .filter(|(_, radials)| !radials.is_empty())
+        .map(|(&elevation_number, radials)| Sweep {
+            elevation_number,
+            elevation_angle: radials
+                .iter()
+                .map(|radial| radial.header().elev())
+                .sum::<f32>()
+                / radials.len() as f32,
+            start_time: radial_time(&radials[0]),
+            end_time: radial_time(&radials[radials.len() - 1]),
+            waveform: if radials
+                .iter()
+                .any(|radial| radial.velocity_data().is_some())
+            {
+                Waveform::Doppler
+            } else {
+                Waveform::Surveillance
+            },
+            radials,
+        })
+        .collect::<Vec<_>>();
+
+    sweeps.sort_by_key(|sweep| (sweep.start_time, sweep.elevation_number));
+    sweeps
+}
 
-            let mut scaled_gates: Vec<f32> = Vec::new();
-            for raw_gate in raw_gates {
-                if raw_gate == 0 {
-                    scaled_gates.push(BELOW_THRESHOLD);
-                } else if raw_gate == 1 {
-                    scaled_gates.push(MOMENT_FOLDED);
-                } else {
-                    let scale = data_moment.data().scale();
-                    let offset = data_moment.data().offset();
-
-                    let scaled_gate = if scale == 0.0 {
-                        raw_gate as f32
-                    } else {
-                        (raw_gate as f32 - offset) / scale
-                    };
-
-                    scaled_gates.push(scaled_gate);
-                }
-            }
+/// A summary of what a decoded volume contains, for display alongside the requested parameters.
+pub struct VolumeMetadata {
+    pub radar_id: String,
+    pub coverage_pattern: Option<u16>,
+    /// Site latitude and longitude in degrees.
+    pub location: Option<(f32, f32)>,
+    /// Site elevation above sea level and feedhorn height above the site, in meters.
+    pub site_height_m: Option<u16>,
+    pub feedhorn_height_m: Option<u16>,
+    pub sweeps: Vec<SweepMetadata>,
+}
 
-            for scaled_gate in scaled_gates {
-                if scaled_gate != BELOW_THRESHOLD && scaled_gate > threshold {
-                    let scaled_distance = distance_m * RENDER_RATIO_TO_M;
-                    let position_x = start_angle.cos() * scaled_distance;
-                    let position_y = start_angle.sin() * scaled_distance;
-                    let position_z = (*elevation as f32 * (PI / 180.0)).sin() * scaled_distance;
-
-                    points.push(ColoredPoint::new(
-                        Vector3::new(position_x, position_z, position_y),
-                        scaled_gate,
-                    ));
-                }
+pub struct SweepMetadata {
+    pub elevation_number: u8,
+    pub elevation_angle: f32,
+    pub radial_count: usize,
+    pub azimuth_resolution_deg: f32,
+    pub waveform: Waveform,
+    pub moments: Vec<Moment>,
+    pub start_time: NaiveDateTime,
+    pub end_time: NaiveDateTime,
+}
 
-                distance_m += data_moment.data().data_moment_range_sample_interval() as f32;
-                azimuth += azimuth_spacing;
-            }
+/// Extracts the volume's site details and a summary of each of its sweeps.
+pub fn get_metadata(data: &DataFile) -> VolumeMetadata {
+    let sweeps = sweeps(data);
+
+    let volume_data = sweeps
+        .iter()
+        .flat_map(|sweep| sweep.radials)
+        .find_map(|radial| radial.volume_data());
+
+    VolumeMetadata {
+        radar_id: String::from_utf8_lossy(data.volume_header().radar_id()).to_string(),
+        coverage_pattern: volume_data.map(|volume| volume.volume_coverage_pattern_number()),
+        location: volume_data.map(|volume| (volume.lat(), volume.long())),
+        site_height_m: volume_data.map(|volume| volume.site_height()),
+        feedhorn_height_m: volume_data.map(|volume| volume.feedhorn_height()),
+        sweeps: sweeps
+            .iter()
+            .map(|sweep| SweepMetadata {
+                elevation_number: sweep.elevation_number,
+                elevation_angle: sweep.elevation_angle,
+                radial_count: sweep.radials.len(),
+                azimuth_resolution_deg: azimuth_resolution_deg(sweep.radials[0].header().azm_res()),
+                waveform: sweep.waveform,
+                moments: Moment::ALL
+                    .into_iter()
+                    .filter(|&moment| sweep.has_moment(moment))
+                    .collect(),
+                start_time: sweep.start_time,
+                end_time: sweep.end_time,
+            })
+            .collect(),
+    }
+}
+
+/// Picks the sweeps to render per the selection policy, keeping at most one sweep per elevation
+/// angle unless all sweeps are requested.
+pub fn select_sweeps<'a>(
+    sweeps: &'a [Sweep<'a>],
+    selection: SweepSelection,
+    moment: Moment,
+) -> Vec<&'a Sweep<'a>> {
+    let candidates = sweeps
+        .iter()
+        .filter(|sweep| selection != SweepSelection::ByMoment || sweep.has_moment(moment));
+
+    if selection == SweepSelection::All {
+        return candidates.collect();
+    }
+
+    // Sweeps are in collection order, so a later sweep at a repeated angle replaces earlier ones
+    let mut selected: Vec<&Sweep> = Vec::new();
+    for sweep in candidates {
+        selected.retain(|kept| {
+            (kept.elevation_angle - sweep.elevation_angle).abs() >= SWEEP_ANGLE_TOLERANCE_DEG
+        });
+        selected.push(sweep);
+    }
+
+    selected
+}
+
+/// A radial's collection time from its modified Julian date (day 1 is 1970-01-01) and
+/// milliseconds past midnight.
+fn radial_time(radial: &Message31) -> NaiveDateTime {
+    let epoch = NaiveDate::from_ymd_opt(1969, 12, 31).unwrap();
+    let date = epoch + Duration::days(radial.header().ray_date() as i64);
+
+    date.and_hms_opt(0, 0, 0).unwrap() + Duration::milliseconds(radial.header().ray_time() as i64)
+}
+
+pub fn get_points(
+    data: &DataFile,
+    moment: Moment,
+    threshold: f32,
+    refraction_factor: f32,
+    sweep_selection: SweepSelection,
+    cancel: &CancellationToken,
+) -> Result<Vec<ColoredPoint>> {
+    let sweeps = sweeps(data);
+    let selected_sweeps = select_sweeps(&sweeps, sweep_selection, moment);
+    println!(
+        "Selected {} of {} sweeps.",
+        selected_sweeps.len(),
+        sweeps.len()
+    );
+
+    for sweep in &selected_sweeps {
+        println!(
+            "Sweep {} at {:.2}° ({:?}) started {}.",
+            sweep.elevation_number, sweep.elevation_angle, sweep.waveform, sweep.start_time
+        );
+    }
+
+    let radials = selected_sweeps
+        .iter()
+        .flat_map(|sweep| sweep.radials)
+        .collect::<Vec<_>>();
+
+    // Each task appends its radials' points to one buffer and reuses one set of gate buffers, and
+    // rayon keeps the buffers in radial order so output matches a serial walk
+    let point_buffers = radials
+        .par_iter()
+        .try_fold(
+            || (Vec::new(), GateBuffers::default()),
+            |(mut points, mut buffers), radial| {
+                check_cancelled(cancel)?;
+                push_radial_points(
+                    radial,
+                    moment,
+                    threshold,
+                    refraction_factor,
+                    &mut buffers,
+                    &mut points,
+                )?;
+                Ok((points, buffers))
+            },
+        )
+        .map(|result| result.map(|(points, _)| points))
+        .collect::<Result<Vec<Vec<ColoredPoint>>>>()?;
+
+    Ok(point_buffers.concat())
+}
+
+/// Scratch space for a radial's gate values, reused across radials to avoid allocating per radial.
+#[derive(Default)]
+struct GateBuffers {
+    scaled: Vec<f32>,
+    derived: Vec<f32>,
+}
+
+/// Appends the points for a radial's gates which pass the threshold or are range folded.
+fn push_radial_points(
+    radial: &Message31,
+    moment: Moment,
+    threshold: f32,
+    refraction_factor: f32,
+    buffers: &mut GateBuffers,
+    points: &mut Vec<ColoredPoint>,
+) -> Result<()> {
+    // Azimuths are clockwise from north while render angles are counterclockwise from +x
+    let azimuth_angle = (radial.header().azm() - 90.0) * (PI / 180.0);
+    let azimuth_width = azimuth_resolution_deg(radial.header().azm_res()) * (PI / 180.0);
+    let elevation_angle = radial.header().elev() * (PI / 180.0);
+
+    // Not every sweep carries every moment, e.g. velocity is absent from surveillance cuts
+    let Some(data_moment) = moment_data(radial, moment) else {
+        return Ok(());
+    };
+
+    let gate_length_m = data_moment.data().data_moment_range_sample_interval() as f32;
+    let first_gate_range_m = data_moment.data().data_moment_range() as f32;
+
+    scaled_gate_values(data_moment, &mut buffers.scaled)?;
+
+    let scaled_gates = if moment == Moment::SpecificDifferentialPhase {
+        specific_differential_phase(
+            &buffers.scaled,
+            gate_length_m / 1000.0,
+            &mut buffers.derived,
+        );
+        &buffers.derived
+    } else {
+        &buffers.scaled
+    };
+
+    for (gate_index, &scaled_gate) in scaled_gates.iter().enumerate() {
+        let folded = scaled_gate == MOMENT_FOLDED;
+        if folded
+            || scaled_gate != BELOW_THRESHOLD && moment.passes_threshold(scaled_gate, threshold)
+        {
+            let gate = GateGeometry {
+                azimuth_rad: azimuth_angle,
+                elevation_rad: elevation_angle,
+                range_m: first_gate_range_m + gate_index as f32 * gate_length_m,
+                azimuth_width_rad: azimuth_width,
+                beam_width_rad: BEAM_WIDTH_DEG * (PI / 180.0),
+                gate_length_m,
+            };
+
+            let height_m = beam_height_m(gate.range_m, elevation_angle, refraction_factor);
+            let ground_range_m =
+                ground_range_m(gate.range_m, elevation_angle, height_m, refraction_factor);
+            let position = render_position(azimuth_angle, ground_range_m, height_m);
+
+            points.push(if folded {
+                ColoredPoint::new_folded(position, gate)
+            } else {
+                ColoredPoint::new(position, gate, scaled_gate)
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Decodes a radial's azimuthal resolution code (1 = 0.5°, 2 = 1.0°) into degrees.
+pub fn azimuth_resolution_deg(code: u8) -> f32 {
+    match code {
+        1 => 0.5,
+        _ => 1.0,
+    }
+}
+
+/// The radial's data block for the specified moment, if it was collected. Specific differential
+/// phase is derived from the differential phase block.
+pub fn moment_data(radial: &Message31, moment: Moment) -> Option<&DataMoment> {
+    match moment {
+        Moment::Reflectivity => radial.reflectivity_data(),
+        Moment::Velocity => radial.velocity_data(),
+        Moment::SpectrumWidth => radial.sw_data(),
+        Moment::DifferentialReflectivity => radial.zdr_data(),
+        Moment::CorrelationCoefficient => radial.rho_data(),
+        Moment::DifferentialPhase | Moment::SpecificDifferentialPhase => radial.phi_data(),
+    }
+}
+
+/// Unpacks a moment's gate values from its 8 or 16-bit (big-endian) data words into the buffer,
+/// scaled to the moment's units or marked below threshold/range folded.
+fn scaled_gate_values(data_moment: &DataMoment, scaled_gates: &mut Vec<f32>) -> Result<()> {
+    let gate_count = data_moment.data().number_data_moment_gates() as usize;
+    let moment_data = data_moment.moment_data();
+    let scale = data_moment.data().scale();
+    let offset = data_moment.data().offset();
+
+    let scale_gate = |raw_gate: u16| match raw_gate {
+        0 => BELOW_THRESHOLD,
+        1 => MOMENT_FOLDED,
+        _ if scale == 0.0 => raw_gate as f32,
+        _ => (raw_gate as f32 - offset) / scale,
+    };
+
+    scaled_gates.clear();
+    match data_moment.data().data_word_size() {
+        8 => scaled_gates.extend(
+            moment_data
+                .iter()
+                .take(gate_count)
+                .map(|word| scale_gate(*word as u16)),
+        ),
+        16 => scaled_gates.extend(
+            moment_data
+                .chunks_exact(2)
+                .take(gate_count)
+                .map(|word| scale_gate(u16::from_be_bytes([word[0], word[1]]))),
+        ),
+        word_size => {
+            return Err(Error::DecodeError(format!(
+                "unsupported {}-bit data words in {} moment",
+                word_size,
+                String::from_utf8_lossy(data_moment.data().data_name()),
+            )))
+        }
+    }
+
+    Ok(())
+}
+
+/// Number of gates on either side of a gate used to fit the differential phase slope.
+const KDP_HALF_WINDOW: usize = 4;
+
+/// Estimates specific differential phase (deg/km) along a radial as half the least-squares slope of
+/// differential phase over a sliding window of gates, writing into the buffer. Gates without
+/// enough valid neighbors are marked below threshold.
+pub fn specific_differential_phase(
+    phi_gates: &[f32],
+    gate_interval_km: f32,
+    kdp_gates: &mut Vec<f32>,
+) {
+    let is_valid = |value: f32| value != BELOW_THRESHOLD && value != MOMENT_FOLDED;
+
+    kdp_gates.clear();
+    kdp_gates.extend((0..phi_gates.len()).map(|center| {
+        if !is_valid(phi_gates[center]) {
+            return phi_gates[center];
+        }
+
+        let start = center.saturating_sub(KDP_HALF_WINDOW);
+        let end = (center + KDP_HALF_WINDOW + 1).min(phi_gates.len());
+
+        let samples = || {
+            (start..end)
+                .filter(|&gate| is_valid(phi_gates[gate]))
+                .map(|gate| (gate as f32 * gate_interval_km, phi_gates[gate]))
+        };
+
+        let sample_count = samples().count();
+        if sample_count <= KDP_HALF_WINDOW {
+            return BELOW_THRESHOLD;
         }
+
+        let count = sample_count as f32;
+        let mean_range = samples().map(|(range, _)| range).sum::<f32>() / count;
+        let mean_phi = samples().map(|(_, phi)| phi).sum::<f32>() / count;
+
+        let (covariance, variance) =
+            samples().fold((0.0, 0.0), |(covariance, variance), (range, phi)| {
+                let range_delta = range - mean_range;
+                (
+                    covariance + range_delta * (phi - mean_phi),
+                    variance + range_delta * range_delta,
+                )
+            });
+
+        0.5 * covariance / variance
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn file(date: &str, identifier: &str) -> FileMetadata {
+        FileMetadata::new(
+            "KDMX".to_string(),
+            date.parse().unwrap(),
+            identifier.to_string(),
+        )
+    }
+
+    fn target(date: &str, time: &str) -> NaiveDateTime {
+        date.parse::<NaiveDate>()
+            .unwrap()
+            .and_time(time.parse().unwrap())
+    }
+
+    /// Exactly the middle file's scan time.
+    fn target_exact() -> NaiveDateTime {
+        target("2022-03-05", "23:30:03")
+    }
+
+    fn files() -> Vec<FileMetadata> {
+        vec![
+            file("2022-03-05", "KDMX20220305_232500_V06"),
+            file("2022-03-05", "KDMX20220305_233003_V06"),
+            file("2022-03-05", "KDMX20220305_233508_V06"),
+        ]
     }
 
-    points
+    fn selected(
+        files: &[FileMetadata],
+        target: &NaiveDateTime,
+        selection: ScanSelection,
+        tolerance: Option<u32>,
+    ) -> Option<String> {
+        nearest_file(files, target, selection, tolerance)
+            .ok()
+            .map(|file| file.identifier().to_string())
+    }
+
+    #[test]
+    fn nearest_picks_closest_before_or_after() {
+        let files = files();
+
+        // 2 minutes after 23:30:03 is closer to 23:35:08 than to 23:30:03
+        assert_eq!(
+            selected(
+                &files,
+                &target("2022-03-05", "23:33:00"),
+                ScanSelection::Nearest,
+                None
+            ),
+            Some("KDMX20220305_233508_V06".to_string())
+        );
+        assert_eq!(
+            selected(
+                &files,
+                &target("2022-03-05", "23:32:00"),
+                ScanSelection::Nearest,
+                None
+            ),
+            Some("KDMX20220305_233003_V06".to_string())
+        );
+        assert_eq!(
+            selected(
+                &files,
+                &target("2022-03-05", "23:27:00"),
+                ScanSelection::Nearest,
+                None
+            ),
+            Some("KDMX20220305_232500_V06".to_string())
+        );
+    }
+
+    #[test]
+    fn at_or_before_and_after_respect_direction() {
+        let files = files();
+        let target = target("2022-03-05", "23:33:00");
+
+        assert_eq!(
+            selected(&files, &target, ScanSelection::AtOrBefore, None),
+            Some("KDMX20220305_233003_V06".to_string())
+        );
+        assert_eq!(
+            selected(&files, &target, ScanSelection::AtOrAfter, None),
+            Some("KDMX20220305_233508_V06".to_string())
+        );
+
+        // An exact match qualifies either way
+        let exact = target_exact();
+        assert_eq!(
+            selected(&files, &exact, ScanSelection::AtOrBefore, None),
+            Some("KDMX20220305_233003_V06".to_string())
+        );
+        assert_eq!(
+            selected(&files, &exact, ScanSelection::AtOrAfter, None),
+            Some("KDMX20220305_233003_V06".to_string())
+        );
+    }
+
+    #[test]
+    fn nothing_qualifying_is_an_error() {
+        let files = files();
+
+        let result = nearest_file(
+            &files,
+            &target("2022-03-05", "23:20:00"),
+            ScanSelection::AtOrBefore,
+            None,
+        );
+        assert!(matches!(result, Err(Error::ScanNotFoundError(_))));
+
+        let result = nearest_file(
+            &files,
+            &target("2022-03-05", "23:40:00"),
+            ScanSelection::AtOrAfter,
+            None,
+        );
+        assert!(matches!(result, Err(Error::ScanNotFoundError(_))));
+
+        let result = nearest_file(&[], &target_exact(), ScanSelection::Nearest, None);
+        assert!(matches!(result, Err(Error::ScanNotFoundError(_))));
+    }
+
+    #[test]
+    fn tolerance_limits_the_offset() {
+        let files = files();
+        let target = target("2022-03-05", "23:45:00");
+
+        // The latest scan is 9 minutes 52 seconds before the target
+        assert_eq!(
+            selected(&files, &target, ScanSelection::Nearest, Some(9)),
+            None
+        );
+        assert_eq!(
+            selected(&files, &target, ScanSelection::Nearest, Some(10)),
+            Some("KDMX20220305_233508_V06".to_string())
+        );
+
+        // Tolerance applies after the direction, so a closer scan on the other side doesn't count
+        let target = target_exact() + Duration::seconds(60);
+        assert_eq!(
+            selected(&files, &target, ScanSelection::AtOrAfter, Some(4)),
+            None
+        );
+        assert_eq!(
+            selected(&files, &target, ScanSelection::AtOrAfter, Some(5)),
+            Some("KDMX20220305_233508_V06".to_string())
+        );
+    }
+
+    #[test]
+    fn metadata_files_are_ignored() {
+        let files = vec![
+            file("2022-03-05", "KDMX20220305_233003_V06_MDM"),
+            file("2022-03-05", "KDMX20220305_233508_V06"),
+        ];
+
+        assert_eq!(
+            selected(&files, &target_exact(), ScanSelection::Nearest, None),
+            Some("KDMX20220305_233508_V06".to_string())
+        );
+
+        let result = nearest_file(&files[..1], &target_exact(), ScanSelection::Nearest, None);
+        assert!(matches!(result, Err(Error::ScanNotFoundError(_))));
+    }
+
+    #[test]
+    fn nearest_scan_can_be_on_the_next_day() {
+        let files = vec![
+            file("2022-03-05", "KDMX20220305_235000_V06"),
+            file("2022-03-06", "KDMX20220306_000100_V06"),
+        ];
+
+        assert_eq!(
+            selected(
+                &files,
+                &target("2022-03-05", "23:58:00"),
+                ScanSelection::Nearest,
+                None
+            ),
+            Some("KDMX20220306_000100_V06".to_string())
+        );
+        assert_eq!(
+            selected(
+                &files,
+                &target("2022-03-05", "23:58:00"),
+                ScanSelection::AtOrBefore,
+                None
+            ),
+            Some("KDMX20220305_235000_V06".to_string())
+        );
+    }
 }
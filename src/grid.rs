@@ -0,0 +1,192 @@
+use crate::beam::{beam_height_m, ground_range_m};
+use crate::data::{ColoredPoint, BEAM_WIDTH_DEG};
+use crate::param::{GridInterpolation, GridParams, Moment};
+use crate::result::Result;
+use crate::spatial::SpatialIndex;
+use crate::state::check_cancelled;
+use rayon::prelude::*;
+use three_d::{InnerSpace, Vector3};
+use tokio_util::sync::CancellationToken;
+
+/// Radius of influence in beam widths at a cell's range, so it spans the gaps between radials and
+/// sweeps which widen with range.
+const ROI_BEAM_WIDTHS: f32 = 2.0;
+
+/// A moment interpolated onto a regular radar-centered grid. Horizontal axes follow the render's x
+/// and z axes in meters of ground range from the radar, and the vertical axis is height above the
+/// earth's surface, so grid levels are constant-altitude (CAPPI) surfaces. Cells without gates in
+/// their radius of influence are NaN.
+pub struct Grid {
+    pub moment: Moment,
+    /// Number of cells along x, y (up), and z.
+    pub dimensions: [usize; 3],
+    /// Position of the first cell's center in meters.
+    pub origin_m: Vector3<f32>,
+    pub horizontal_resolution_m: f32,
+    pub vertical_resolution_m: f32,
+    values: Vec<f32>,
+}
+
+impl Grid {
+    /// Interpolates the points' values onto a grid per the parameters. Range-folded points are
+    /// ignored, and reflectivity is averaged in linear units.
+    pub fn from_points(
+        points: &[ColoredPoint],
+        moment: Moment,
+        params: &GridParams,
+        refraction_factor: f32,
+        cancel: &CancellationToken,
+    ) -> Result<Grid> {
+        let points = points.iter().filter(|p| !p.folded).collect::<Vec<_>>();
+
+        let grid_positions = points
+            .iter()
+            .map(|point| grid_position(point, refraction_factor))
+            .collect::<Vec<_>>();
+        let index = SpatialIndex::from_positions(grid_positions, params.horizontal_resolution_m);
+
+        let extent_m = params.extent_km * 1000.0;
+
+        let mut grid = Grid {
+            moment,
+            dimensions: params.dimensions(),
+            origin_m: Vector3::new(
+                -extent_m + params.horizontal_resolution_m / 2.0,
+                params.vertical_resolution_m / 2.0,
+                -extent_m + params.horizontal_resolution_m / 2.0,
+            ),
+            horizontal_resolution_m: params.horizontal_resolution_m,
+            vertical_resolution_m: params.vertical_resolution_m,
+            values: Vec::new(),
+        };
+
+        let linear = moment == Moment::Reflectivity;
+        let cell_count = grid.dimensions.iter().product::<usize>();
+
+        grid.values = (0..cell_count)
+            .into_par_iter()
+            .map(|cell_index| {
+                if cell_index % grid.dimensions[0] == 0 {
+                    check_cancelled(cancel)?;
+                }
+
+                let center = grid.cell_center(grid.cell_coordinates(cell_index));
+                let roi_m = (center.magnitude() * ROI_BEAM_WIDTHS * BEAM_WIDTH_DEG.to_radians())
+                    .max(params.horizontal_resolution_m);
+
+                Ok(interpolate(
+                    &index,
+                    &points,
+                    center,
+                    roi_m,
+                    params.interpolation,
+                    linear,
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(grid)
+    }
+
+    /// Number of cells with data.
+    pub fn filled_count(&self) -> usize {
+        self.values.iter().filter(|value| !value.is_nan()).count()
+    }
+
+    /// The value of a cell, NaN if it has no data.
+    pub fn value(&self, [x, y, z]: [usize; 3]) -> f32 {
+        self.values[(z * self.dimensions[1] + y) * self.dimensions[0] + x]
+    }
+
+    /// The center of a cell in grid meters.
+    pub fn cell_center(&self, [x, y, z]: [usize; 3]) -> Vector3<f32> {
+        self.origin_m
+            + Vector3::new(
+                x as f32 * self.horizontal_resolution_m,
+                y as f32 * self.vertical_resolution_m,
+                z as f32 * self.horizontal_resolution_m,
+            )
+    }
+
+    fn cell_coordinates(&self, cell_index: usize) -> [usize; 3] {
+        let x = cell_index % self.dimensions[0];
+        let y = cell_index / self.dimensions[0] % self.dimensions[1];
+        let z = cell_index / (self.dimensions[0] * self.dimensions[1]);
+        [x, y, z]
+    }
+}
+
+/// A gate's position in grid coordinates: ground range along its azimuth and height above the
+/// surface, in meters.
+fn grid_position(point: &ColoredPoint, refraction_factor: f32) -> Vector3<f32> {
+    let gate = &point.gate;
+    let height_m = beam_height_m(gate.range_m, gate.elevation_rad, refraction_factor);
+    let ground_range_m = ground_range_m(
+        gate.range_m,
+        gate.elevation_rad,
+        height_m,
+        refraction_factor,
+    );
+
+    Vector3::new(
+        ground_range_m * gate.azimuth_rad.cos(),
+        height_m,
+        ground_range_m * gate.azimuth_rad.sin(),
+    )
+}
+
+/// Interpolates the value at a cell center from the gates within the radius of influence, NaN if
+/// there are none.
+fn interpolate(
+    index: &SpatialIndex,
+    points: &[&ColoredPoint],
+    center: Vector3<f32>,
+    roi_m: f32,
+    interpolation: GridInterpolation,
+    linear: bool,
+) -> f32 {
+    if interpolation == GridInterpolation::Nearest {
+        return index
+            .nearest(center, 1, Some(roi_m))
+            .first()
+            .map_or(f32::NAN, |(nearest, _)| points[*nearest].value);
+    }
+
+    let roi_squared = roi_m * roi_m;
+    let (mut weighted_sum, mut weight_sum) = (0.0, 0.0);
+
+    index.for_each_within_radius(center, roi_m, |neighbor| {
+        let distance_squared = (index.position(neighbor) - center).magnitude2();
+        let weight = match interpolation {
+            GridInterpolation::Cressman => {
+                (roi_squared - distance_squared) / (roi_squared + distance_squared)
+            }
+            // A Gaussian falling to e^-4 at the radius of influence
+            GridInterpolation::Barnes => (-4.0 * distance_squared / roi_squared).exp(),
+            GridInterpolation::Nearest => unreachable!(),
+        };
+
+        let value = points[neighbor].value;
+        weighted_sum += weight * if linear { dbz_to_linear(value) } else { value };
+        weight_sum += weight;
+    });
+
+    if weight_sum <= 0.0 {
+        return f32::NAN;
+    }
+
+    let value = weighted_sum / weight_sum;
+    if linear {
+        linear_to_dbz(value)
+    } else {
+        value
+    }
+}
+
+fn dbz_to_linear(dbz: f32) -> f32 {
+    10f32.powf(dbz / 10.0)
+}
+
+fn linear_to_dbz(linear: f32) -> f32 {
+    10.0 * linear.log10()
+}
@@ -1,34 +1,134 @@
+use crate::cache::Cache;
+use crate::cli::{
+    parse_budget, parse_date, parse_file, parse_positive, parse_sampling, parse_site, parse_time,
+    parse_tolerance, parse_value,
+};
+use crate::color_table::{select_color_table, ColorTable};
 use crate::param::ClusteringMode::{DBSCAN, KNN};
 use crate::param::InteractionMode::{ManualOrbit, Orbit};
-use crate::param::PointColorMode::{Density, Hybrid, Raw};
-use crate::param::{ClusteringMode, DataParams, VisParams};
+use crate::param::PointColorMode::{Cluster, Density, Hybrid, Raw};
+use crate::param::{
+    ClusteringMode, DataParams, DataSource, DensityColormap, GateRenderMode, GridInterpolation,
+    GridParams, Moment, SamplingMode, ScanSelection, SweepSelection, TransferPreset, VisParams,
+};
 use crate::state::State;
+use crate::transfer::{ControlPoint, TransferFunction};
 use crate::CONTROL_PANEL_WIDTH;
-use chrono::{NaiveDate, NaiveTime};
-use std::str::FromStr;
-use three_d::egui::{Align, Color32, Layout, SidePanel, Ui};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use three_d::egui::{
+    pos2, vec2, Align, Color32, Layout, Pos2, Rect, ScrollArea, Sense, Shape, SidePanel, Slider,
+    Stroke, TopBottomPanel, Ui,
+};
 use three_d::{Context, FrameInput, GUI};
 
+const TRANSFER_EDITOR_HEIGHT: f32 = 80.0;
+
+/// Height of the transfer function editor's strip of unfaded colors.
+const TRANSFER_STRIP_HEIGHT: f32 = 6.0;
+
+const TRANSFER_POINT_RADIUS: f32 = 4.0;
+
+/// How close in pixels the pointer must be to pick a transfer function control point.
+const TRANSFER_PICK_DISTANCE: f32 = 8.0;
+
 pub struct Gui {
     gui: Option<GUI>,
     site_string: String,
     date_string: String,
     time_string: String,
+    scan_selection: ScanSelection,
+    tolerance_string: String,
+    sweep_selection: SweepSelection,
+    moment: Moment,
+    threshold_string: String,
+    refraction_string: String,
+    sampling_mode: SamplingMode,
     sampling_string: String,
+    budget_string: String,
+    voxel_size_string: String,
+    keep_above_string: String,
     clustering_mode: ClusteringMode,
     clustering_t_string: String,
+    density_radius_string: String,
+    grid_enabled: bool,
+    grid_interpolation: GridInterpolation,
+    grid_extent_string: String,
+    grid_top_string: String,
+    grid_resolution_string: String,
+    grid_vertical_string: String,
+    source: DataSource,
+    local_files: Vec<PathBuf>,
+    file_string: String,
+    input_error: Option<String>,
+    /// Moment the transfer function was made for.
+    transfer_moment: Moment,
+    transfer_selected: Option<usize>,
+    transfer_dragging: Option<usize>,
+    transfer_path_string: String,
+    transfer_error: Option<String>,
+    /// Tables loaded from the command line or the GUI, for any moment.
+    color_tables: Vec<ColorTable>,
+    color_table_path_string: String,
+    color_table_error: Option<String>,
+    /// The current moment's built-in table, kept rather than parsed every frame.
+    builtin_color_table: ColorTable,
+    /// Isosurface levels from the command line, offered alongside the color table's levels
+    /// whether or not they're enabled.
+    isosurface_levels: Vec<f32>,
 }
 
 impl Gui {
-    pub fn new(context: &Context, parameters: &DataParams) -> Self {
+    pub fn new(
+        context: &Context,
+        parameters: &DataParams,
+        local_files: &[PathBuf],
+        color_tables: &[ColorTable],
+        isosurface_levels: &[f32],
+    ) -> Self {
+        let grid = parameters.grid.unwrap_or_default();
+
         Gui {
             gui: Some(GUI::new(context)),
             site_string: parameters.site.to_string(),
             date_string: parameters.date.to_string(),
             time_string: parameters.time.to_string(),
+            scan_selection: parameters.scan_selection,
+            tolerance_string: parameters
+                .scan_tolerance
+                .map_or(String::new(), |minutes| minutes.to_string()),
+            sweep_selection: parameters.sweep_selection,
+            moment: parameters.moment,
+            threshold_string: parameters.threshold.to_string(),
+            refraction_string: parameters.refraction_factor.to_string(),
+            sampling_mode: parameters.sampling_mode,
             sampling_string: parameters.sampling.to_string(),
+            budget_string: parameters.point_budget.to_string(),
+            voxel_size_string: parameters.voxel_size_m.to_string(),
+            keep_above_string: parameters.keep_above.to_string(),
             clustering_mode: parameters.clustering_mode,
             clustering_t_string: parameters.clustering_threshold.to_string(),
+            density_radius_string: parameters.density_radius_m.to_string(),
+            grid_enabled: parameters.grid.is_some(),
+            grid_interpolation: grid.interpolation,
+            grid_extent_string: grid.extent_km.to_string(),
+            grid_top_string: grid.top_km.to_string(),
+            grid_resolution_string: grid.horizontal_resolution_m.to_string(),
+            grid_vertical_string: grid.vertical_resolution_m.to_string(),
+            source: parameters.source.clone(),
+            local_files: local_files.to_vec(),
+            file_string: String::new(),
+            input_error: None,
+            transfer_moment: parameters.moment,
+            transfer_selected: None,
+            transfer_dragging: None,
+            transfer_path_string: "transfer.txt".to_string(),
+            transfer_error: None,
+            color_tables: color_tables.to_vec(),
+            color_table_path_string: String::new(),
+            color_table_error: None,
+            builtin_color_table: ColorTable::builtin(parameters.moment),
+            isosurface_levels: isosurface_levels.to_vec(),
         }
     }
 
@@ -36,6 +136,7 @@ impl Gui {
         &mut self,
         frame_input: &mut FrameInput,
         state: &State,
+        cache: &Mutex<Cache>,
         vis_params: &VisParams,
         data_params: &DataParams,
     ) -> (Option<VisParams>, Option<DataParams>) {
@@ -54,12 +155,23 @@ impl Gui {
                     .exact_width(CONTROL_PANEL_WIDTH)
                     .resizable(false)
                     .show(gui_context, |ui| {
-                        ui.add_space(10.0);
+                        // Keep applying and the status in view however far the controls scroll
+                        TopBottomPanel::bottom("side_panel_status")
+                            .show_separator_line(false)
+                            .show_inside(ui, |ui| {
+                                new_data_params = self.update_apply(ui);
+                                self.update_status(ui, state);
+                            });
 
-                        new_vis_params = self.update_vis_params(ui, vis_params);
-                        self.update_current_params(ui, data_params);
-                        new_data_params = self.update_data_params(ui);
-                        self.update_status(ui, state);
+                        ScrollArea::vertical().show(ui, |ui| {
+                            ui.add_space(10.0);
+
+                            new_vis_params = self.update_vis_params(ui, vis_params, data_params);
+                            self.update_current_params(ui, data_params);
+                            self.update_metadata(ui, state);
+                            self.update_data_params(ui);
+                            self.update_cache(ui, cache);
+                        });
                     });
             },
         );
@@ -68,9 +180,27 @@ impl Gui {
         (new_vis_params, new_data_params)
     }
 
-    fn update_vis_params(&mut self, ui: &mut Ui, vis_params: &VisParams) -> Option<VisParams> {
+    fn update_vis_params(
+        &mut self,
+        ui: &mut Ui,
+        vis_params: &VisParams,
+        data_params: &DataParams,
+    ) -> Option<VisParams> {
         let mut new_vis_params = vis_params.clone();
 
+        // Color tables and transfer functions are in the moment's units, so a new moment starts
+        // over from its table and the preset
+        if data_params.moment != self.transfer_moment {
+            new_vis_params.color_table = select_color_table(data_params.moment, &self.color_tables);
+            new_vis_params.transfer_function = TransferFunction::preset(
+                vis_params.transfer_preset,
+                data_params.moment,
+                &new_vis_params.color_table,
+            );
+            self.transfer_moment = data_params.moment;
+            self.transfer_selected = None;
+        }
+
         ui.heading("Visualization");
 
         ui.add_space(10.0);
@@ -85,6 +215,106 @@ impl Gui {
         ui.radio_value(&mut new_vis_params.point_color_mode, Raw, "Raw");
         ui.radio_value(&mut new_vis_params.point_color_mode, Density, "Density");
         ui.radio_value(&mut new_vis_params.point_color_mode, Hybrid, "Hybrid");
+        ui.radio_value(&mut new_vis_params.point_color_mode, Cluster, "Cluster");
+        if new_vis_params.point_color_mode == Cluster
+            && data_params.clustering_mode == ClusteringMode::None
+        {
+            ui.colored_label(Color32::from_rgb(180, 180, 180), "Requires Cluster Mode");
+        }
+
+        ui.add_space(10.0);
+
+        ui.label("Density Colormap");
+        ui.radio_value(
+            &mut new_vis_params.density_colormap,
+            DensityColormap::Viridis,
+            "Viridis",
+        );
+        ui.radio_value(
+            &mut new_vis_params.density_colormap,
+            DensityColormap::Inferno,
+            "Inferno",
+        );
+        ui.radio_value(
+            &mut new_vis_params.density_colormap,
+            DensityColormap::Grayscale,
+            "Grayscale",
+        );
+
+        ui.add_space(10.0);
+
+        self.update_color_table(ui, &mut new_vis_params, data_params.moment);
+
+        ui.add_space(10.0);
+
+        ui.label("Gate Rendering");
+        ui.radio_value(
+            &mut new_vis_params.gate_render_mode,
+            GateRenderMode::Points,
+            "Points",
+        );
+        ui.radio_value(
+            &mut new_vis_params.gate_render_mode,
+            GateRenderMode::Volumes,
+            "Sample Volumes",
+        );
+        ui.add_enabled_ui(data_params.grid.is_some(), |ui| {
+            ui.checkbox(&mut new_vis_params.direct_volume, "Ray-Marched Grid");
+        });
+        if data_params.grid.is_none() {
+            ui.colored_label(Color32::from_rgb(180, 180, 180), "Requires Grid Volume");
+        } else if new_vis_params.direct_volume {
+            ui.label("Step (m)");
+            ui.add(Slider::new(&mut new_vis_params.volume_step_m, 50.0..=5000.0).logarithmic(true));
+            ui.label("Opacity Scale");
+            ui.add(
+                Slider::new(&mut new_vis_params.volume_opacity_scale, 0.05..=20.0)
+                    .logarithmic(true),
+            );
+
+            ui.collapsing("Transfer Function", |ui| {
+                self.update_transfer_function(ui, &mut new_vis_params, data_params.moment);
+            });
+        }
+
+        ui.add_space(10.0);
+
+        ui.checkbox(&mut new_vis_params.show_range_folded, "Show Range Folded");
+
+        ui.add_space(10.0);
+
+        ui.label(format!("Isosurfaces ({})", data_params.moment.units()));
+        if data_params.grid.is_none() {
+            ui.colored_label(Color32::from_rgb(180, 180, 180), "Requires Grid Volume");
+        }
+
+        // The color table's levels within the moment's range plus any others from the command line
+        let (min, max) = data_params.moment.value_range();
+        let mut levels = new_vis_params
+            .color_table
+            .levels()
+            .into_iter()
+            .filter(|level| (min..=max).contains(level))
+            .collect::<Vec<_>>();
+        levels.extend(&self.isosurface_levels);
+        levels.sort_by(f32::total_cmp);
+        levels.dedup();
+
+        ui.horizontal_wrapped(|ui| {
+            for level in levels {
+                let mut enabled = new_vis_params.isosurface_levels.contains(&level);
+                if ui.checkbox(&mut enabled, level.to_string()).changed() {
+                    if enabled {
+                        new_vis_params.isosurface_levels.push(level);
+                        new_vis_params.isosurface_levels.sort_by(f32::total_cmp);
+                    } else {
+                        new_vis_params
+                            .isosurface_levels
+                            .retain(|other| *other != level);
+                    }
+                }
+            }
+        });
 
         ui.add_space(10.0);
 
@@ -95,12 +325,290 @@ impl Gui {
         }
     }
 
+    /// Selects the moment's color table from the built-in one and those loaded for it or for no
+    /// particular moment, and loads more from .pal files.
+    fn update_color_table(&mut self, ui: &mut Ui, vis_params: &mut VisParams, moment: Moment) {
+        let previous_table = vis_params.color_table.clone();
+
+        ui.label("Color Table");
+
+        if self.builtin_color_table.moment() != Some(moment) {
+            self.builtin_color_table = ColorTable::builtin(moment);
+        }
+        let builtin = &self.builtin_color_table;
+        if ui
+            .radio(&vis_params.color_table == builtin, &builtin.name)
+            .clicked()
+        {
+            vis_params.color_table = builtin.clone();
+        }
+        for table in &self.color_tables {
+            if table.moment().is_none_or(|other| other == moment)
+                && ui
+                    .radio(&vis_params.color_table == table, &table.name)
+                    .clicked()
+            {
+                vis_params.color_table = table.clone();
+            }
+        }
+
+        ui.columns(2, |columns| {
+            columns[0].label("Palette File");
+            columns[1].text_edit_singleline(&mut self.color_table_path_string);
+        });
+
+        if ui.button("Load").clicked() {
+            match ColorTable::load(Path::new(self.color_table_path_string.trim())) {
+                Ok(table) => {
+                    match table.moment() {
+                        Some(other) if other != moment => {
+                            self.color_table_error =
+                                Some(format!("{} is for {}", table.name, other.label()));
+                        }
+                        _ => {
+                            vis_params.color_table = table.clone();
+                            self.color_table_error = None;
+                        }
+                    }
+                    if !self.color_tables.contains(&table) {
+                        self.color_tables.push(table);
+                    }
+                }
+                Err(err) => self.color_table_error = Some(err.to_string()),
+            }
+        }
+
+        if let Some(ref color_table_error) = self.color_table_error {
+            ui.colored_label(Color32::from_rgb(255, 0, 0), color_table_error);
+        }
+
+        // The color table presets follow the table
+        if vis_params.color_table != previous_table
+            && vis_params.transfer_preset != TransferPreset::Grayscale
+        {
+            vis_params.transfer_function = TransferFunction::preset(
+                vis_params.transfer_preset,
+                moment,
+                &vis_params.color_table,
+            );
+            self.transfer_selected = None;
+        }
+    }
+
+    /// Edits the transfer function as a curve of opacity over the moment's value range drawn on a
+    /// preview of its colors. Dragging moves a control point, clicking off the curve adds one, and
+    /// the selected point's color can be edited or the point removed.
+    fn update_transfer_function(
+        &mut self,
+        ui: &mut Ui,
+        vis_params: &mut VisParams,
+        moment: Moment,
+    ) {
+        ui.horizontal_wrapped(|ui| {
+            for preset in TransferPreset::ALL {
+                if ui.button(preset.label()).clicked() {
+                    vis_params.transfer_preset = preset;
+                    vis_params.transfer_function =
+                        TransferFunction::preset(preset, moment, &vis_params.color_table);
+                    self.transfer_selected = None;
+                }
+            }
+        });
+
+        let (min, max) = moment.value_range();
+
+        let (response, painter) = ui.allocate_painter(
+            vec2(ui.available_width(), TRANSFER_EDITOR_HEIGHT),
+            Sense::click_and_drag(),
+        );
+        let rect = response.rect;
+
+        let to_x = |value: f32| rect.left() + (value - min) / (max - min) * rect.width();
+        let to_y = |opacity: f32| rect.bottom() - opacity * rect.height();
+        let to_value =
+            |x: f32| (min + (x - rect.left()) / rect.width() * (max - min)).clamp(min, max);
+        let to_opacity = |y: f32| ((rect.bottom() - y) / rect.height()).clamp(0.0, 1.0);
+        let to_position = |point: &ControlPoint| pos2(to_x(point.value), to_y(point.opacity));
+
+        let points = &mut vis_params.transfer_function.points;
+        let nearest = |points: &[ControlPoint], position: Pos2| {
+            points
+                .iter()
+                .map(|point| to_position(point).distance(position))
+                .enumerate()
+                .filter(|(_, distance)| *distance <= TRANSFER_PICK_DISTANCE)
+                .min_by(|(_, a), (_, b)| a.total_cmp(b))
+                .map(|(index, _)| index)
+        };
+
+        if let Some(position) = response.interact_pointer_pos() {
+            if response.drag_started() {
+                self.transfer_dragging = nearest(points, position);
+                if self.transfer_dragging.is_some() {
+                    self.transfer_selected = self.transfer_dragging;
+                }
+            }
+
+            if response.clicked() {
+                self.transfer_selected = nearest(points, position);
+                if self.transfer_selected.is_none() {
+                    let value = to_value(position.x);
+                    let index = points.partition_point(|point| point.value <= value);
+                    let color = vis_params.transfer_function.sample(value).0;
+
+                    vis_params.transfer_function.points.insert(
+                        index,
+                        ControlPoint {
+                            value,
+                            color,
+                            opacity: to_opacity(position.y),
+                        },
+                    );
+                    self.transfer_selected = Some(index);
+                }
+            }
+        }
+
+        let points = &mut vis_params.transfer_function.points;
+
+        if let (true, Some(index), Some(position)) = (
+            response.dragged(),
+            self.transfer_dragging,
+            response.interact_pointer_pos(),
+        ) {
+            // The ends stay put and the others between their neighbors, keeping the points in order
+            if index > 0 && index < points.len() - 1 {
+                points[index].value =
+                    to_value(position.x).clamp(points[index - 1].value, points[index + 1].value);
+            }
+            points[index].opacity = to_opacity(position.y);
+        }
+
+        if response.drag_released() {
+            self.transfer_dragging = None;
+        }
+
+        // Preview each column's color faded by its opacity, with a solid strip of the colors below
+        let transfer_function = &vis_params.transfer_function;
+        for column in 0..rect.width().ceil() as usize {
+            let x = rect.left() + column as f32;
+            let ((red, green, blue), opacity) = transfer_function.sample(to_value(x + 0.5));
+            let column_rect = |top: f32, bottom: f32| {
+                Rect::from_min_max(pos2(x, top), pos2((x + 1.0).min(rect.right()), bottom))
+            };
+
+            painter.rect_filled(
+                column_rect(rect.top(), rect.bottom() - TRANSFER_STRIP_HEIGHT),
+                0.0,
+                Color32::from_rgba_unmultiplied(red, green, blue, (opacity * 255.0).round() as u8),
+            );
+            painter.rect_filled(
+                column_rect(rect.bottom() - TRANSFER_STRIP_HEIGHT, rect.bottom()),
+                0.0,
+                Color32::from_rgb(red, green, blue),
+            );
+        }
+
+        painter.rect_stroke(rect, 0.0, Stroke::new(1.0, Color32::GRAY));
+        painter.add(Shape::line(
+            transfer_function.points.iter().map(to_position).collect(),
+            Stroke::new(1.5, Color32::WHITE),
+        ));
+
+        for (index, point) in transfer_function.points.iter().enumerate() {
+            let (red, green, blue) = point.color;
+            let outline = if self.transfer_selected == Some(index) {
+                Color32::YELLOW
+            } else {
+                Color32::WHITE
+            };
+
+            painter.circle(
+                to_position(point),
+                TRANSFER_POINT_RADIUS,
+                Color32::from_rgb(red, green, blue),
+                Stroke::new(1.5, outline),
+            );
+        }
+
+        ui.columns(2, |columns| {
+            columns[0].label(min.to_string());
+            columns[1].with_layout(Layout::right_to_left(Align::Center), |ui| {
+                ui.label(max.to_string());
+            });
+        });
+
+        let points = &mut vis_params.transfer_function.points;
+        if let Some(index) = self.transfer_selected.filter(|index| *index < points.len()) {
+            let point = &mut points[index];
+
+            ui.horizontal(|ui| {
+                let mut color = [point.color.0, point.color.1, point.color.2];
+                if ui.color_edit_button_srgb(&mut color).changed() {
+                    point.color = (color[0], color[1], color[2]);
+                }
+
+                ui.colored_label(
+                    Color32::from_rgb(255, 255, 255),
+                    format!("{} {}, {:.2}", point.value, moment.units(), point.opacity),
+                );
+            });
+
+            if points.len() > 2 && ui.button("Remove Point").clicked() {
+                points.remove(index);
+                self.transfer_selected = None;
+            }
+        }
+
+        ui.add_space(10.0);
+
+        ui.columns(2, |columns| {
+            columns[0].label("File");
+            columns[1].text_edit_singleline(&mut self.transfer_path_string);
+        });
+
+        ui.horizontal(|ui| {
+            let path = PathBuf::from(self.transfer_path_string.trim());
+
+            if ui.button("Load").clicked() {
+                match TransferFunction::load(&path, moment) {
+                    Ok(transfer_function) => {
+                        vis_params.transfer_function = transfer_function;
+                        self.transfer_selected = None;
+                        self.transfer_error = None;
+                    }
+                    Err(err) => self.transfer_error = Some(err.to_string()),
+                }
+            }
+
+            if ui.button("Save").clicked() {
+                match vis_params.transfer_function.save(&path, moment) {
+                    Ok(()) => {
+                        println!("Saved transfer function to {}.", path.display());
+                        self.transfer_error = None;
+                    }
+                    Err(err) => self.transfer_error = Some(err.to_string()),
+                }
+            }
+        });
+
+        if let Some(ref transfer_error) = self.transfer_error {
+            ui.colored_label(Color32::from_rgb(255, 0, 0), transfer_error);
+        }
+    }
+
     fn update_current_params(&self, ui: &mut Ui, data_params: &DataParams) {
         ui.heading("Current Parameters");
 
         ui.add_space(10.0);
 
         ui.columns(2, |columns| {
+            columns[0].label("Source");
+            columns[1].colored_label(
+                Color32::from_rgb(255, 255, 255),
+                source_label(&data_params.source),
+            );
+
             columns[0].label("Site");
             columns[1].colored_label(Color32::from_rgb(255, 255, 255), &data_params.site);
 
@@ -116,10 +624,48 @@ impl Gui {
                 data_params.time.to_string(),
             );
 
+            columns[0].label("Scan");
+            columns[1].colored_label(
+                Color32::from_rgb(255, 255, 255),
+                match data_params.scan_tolerance {
+                    Some(minutes) => format!("{:?} ±{}m", data_params.scan_selection, minutes),
+                    None => format!("{:?}", data_params.scan_selection),
+                },
+            );
+
+            columns[0].label("Sweeps");
+            columns[1].colored_label(
+                Color32::from_rgb(255, 255, 255),
+                format!("{:?}", data_params.sweep_selection),
+            );
+
+            columns[0].label("Moment");
+            columns[1].colored_label(Color32::from_rgb(255, 255, 255), data_params.moment.label());
+
+            columns[0].label("Threshold");
+            columns[1].colored_label(
+                Color32::from_rgb(255, 255, 255),
+                format!("{} {}", data_params.threshold, data_params.moment.units()),
+            );
+
+            columns[0].label("Refraction");
+            columns[1].colored_label(
+                Color32::from_rgb(255, 255, 255),
+                format!("{:.3}", data_params.refraction_factor),
+            );
+
             columns[0].label("Sampling");
             columns[1].colored_label(
                 Color32::from_rgb(255, 255, 255),
-                data_params.sampling.to_string(),
+                match data_params.sampling_mode {
+                    SamplingMode::Step => format!("Every {}", data_params.sampling),
+                    SamplingMode::Budget => format!("{} points", data_params.point_budget),
+                    SamplingMode::VoxelGrid => format!("{} m voxels", data_params.voxel_size_m),
+                    SamplingMode::Weighted => format!(
+                        "≥{} + {} points",
+                        data_params.keep_above, data_params.point_budget
+                    ),
+                },
             );
 
             columns[0].label("Cluster Mode");
@@ -133,16 +679,148 @@ impl Gui {
                 Color32::from_rgb(255, 255, 255),
                 data_params.clustering_threshold.to_string(),
             );
+
+            columns[0].label("Density Radius");
+            columns[1].colored_label(
+                Color32::from_rgb(255, 255, 255),
+                format!("{} m", data_params.density_radius_m),
+            );
+
+            columns[0].label("Grid");
+            columns[1].colored_label(
+                Color32::from_rgb(255, 255, 255),
+                match data_params.grid {
+                    Some(grid) => format!(
+                        "{:?} ±{} km, {}/{} m",
+                        grid.interpolation,
+                        grid.extent_km,
+                        grid.horizontal_resolution_m,
+                        grid.vertical_resolution_m
+                    ),
+                    None => "Off".to_string(),
+                },
+            );
+        });
+
+        ui.add_space(10.0);
+    }
+
+    fn update_metadata(&self, ui: &mut Ui, state: &State) {
+        let Some(ref metadata) = state.metadata else {
+            return;
+        };
+
+        ui.collapsing("Volume", |ui| {
+            ui.columns(2, |columns| {
+                columns[0].label("Radar");
+                columns[1].colored_label(Color32::from_rgb(255, 255, 255), &metadata.radar_id);
+
+                columns[0].label("VCP");
+                columns[1].colored_label(
+                    Color32::from_rgb(255, 255, 255),
+                    optional_label(metadata.coverage_pattern),
+                );
+
+                columns[0].label("Location");
+                columns[1].colored_label(
+                    Color32::from_rgb(255, 255, 255),
+                    metadata.location.map_or("-".to_string(), |(lat, long)| {
+                        format!("{:.3}, {:.3}", lat, long)
+                    }),
+                );
+
+                columns[0].label("Elevation");
+                columns[1].colored_label(
+                    Color32::from_rgb(255, 255, 255),
+                    optional_label(metadata.site_height_m.map(|height| format!("{} m", height))),
+                );
+
+                columns[0].label("Feedhorn");
+                columns[1].colored_label(
+                    Color32::from_rgb(255, 255, 255),
+                    optional_label(
+                        metadata
+                            .feedhorn_height_m
+                            .map(|height| format!("{} m", height)),
+                    ),
+                );
+            });
+
+            if let Some(ref grid) = state.grid {
+                ui.label(format!(
+                    "{} grid {}x{}x{}, {} cells with data",
+                    grid.moment.label(),
+                    grid.dimensions[0],
+                    grid.dimensions[2],
+                    grid.dimensions[1],
+                    grid.filled_count()
+                ));
+            }
+
+            ui.add_space(10.0);
+
+            for sweep in &metadata.sweeps {
+                ui.label(format!(
+                    "#{} {:.2}° {:?}",
+                    sweep.elevation_number, sweep.elevation_angle, sweep.waveform
+                ));
+                ui.colored_label(
+                    Color32::from_rgb(255, 255, 255),
+                    format!(
+                        "{} radials @ {}°, {}-{}",
+                        sweep.radial_count,
+                        sweep.azimuth_resolution_deg,
+                        sweep.start_time.format("%H:%M:%S"),
+                        sweep.end_time.format("%H:%M:%S"),
+                    ),
+                );
+                ui.colored_label(
+                    Color32::from_rgb(255, 255, 255),
+                    sweep
+                        .moments
+                        .iter()
+                        .map(|moment| moment.abbreviation())
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                );
+            }
         });
 
         ui.add_space(10.0);
     }
 
-    fn update_data_params(&mut self, ui: &mut Ui) -> Option<DataParams> {
+    fn update_data_params(&mut self, ui: &mut Ui) {
         ui.heading("Update Parameters");
 
         ui.add_space(10.0);
 
+        ui.label("Source");
+        ui.radio_value(&mut self.source, DataSource::Remote, "Download");
+        for path in &self.local_files {
+            let local_source = DataSource::Local(path.clone());
+            let label = source_label(&local_source);
+            ui.radio_value(&mut self.source, local_source, label);
+        }
+
+        ui.columns(2, |columns| {
+            columns[0].text_edit_singleline(&mut self.file_string);
+            if columns[1].button("Add File").clicked() {
+                match parse_file(self.file_string.trim()) {
+                    Ok(path) => {
+                        if !self.local_files.contains(&path) {
+                            self.local_files.push(path.clone());
+                        }
+                        self.source = DataSource::Local(path);
+                        self.file_string.clear();
+                        self.input_error = None;
+                    }
+                    Err(input_error) => self.input_error = Some(input_error),
+                }
+            }
+        });
+
+        ui.add_space(10.0);
+
         ui.columns(2, |columns| {
             columns[0].label("Site");
             columns[1].text_edit_singleline(&mut self.site_string);
@@ -158,62 +836,283 @@ impl Gui {
             columns[1].text_edit_singleline(&mut self.time_string);
         });
 
+        ui.label("Scan Selection");
+        ui.radio_value(&mut self.scan_selection, ScanSelection::Nearest, "Nearest");
+        ui.radio_value(
+            &mut self.scan_selection,
+            ScanSelection::AtOrBefore,
+            "At or Before",
+        );
+        ui.radio_value(
+            &mut self.scan_selection,
+            ScanSelection::AtOrAfter,
+            "At or After",
+        );
+
         ui.columns(2, |columns| {
-            columns[0].label("Sampling");
+            columns[0].label("Tolerance (min)");
+            columns[1].text_edit_singleline(&mut self.tolerance_string);
+        });
+
+        ui.label("Repeated Sweeps");
+        ui.radio_value(&mut self.sweep_selection, SweepSelection::Latest, "Latest");
+        ui.radio_value(&mut self.sweep_selection, SweepSelection::All, "All");
+        ui.radio_value(
+            &mut self.sweep_selection,
+            SweepSelection::ByMoment,
+            "Latest with Moment",
+        );
+
+        ui.label("Moment");
+        let previous_moment = self.moment;
+        for moment in Moment::ALL {
+            ui.radio_value(&mut self.moment, moment, moment.label());
+        }
+        if self.moment != previous_moment {
+            self.threshold_string = self.moment.default_threshold().to_string();
+        }
+
+        ui.columns(2, |columns| {
+            columns[0].label(format!("Threshold ({})", self.moment.units()));
+            columns[1].text_edit_singleline(&mut self.threshold_string);
+        });
+
+        ui.columns(2, |columns| {
+            columns[0].label("Refraction");
+            columns[1].text_edit_singleline(&mut self.refraction_string);
+        });
+
+        ui.label("Sampling");
+        ui.radio_value(&mut self.sampling_mode, SamplingMode::Step, "Every Nth");
+        ui.radio_value(
+            &mut self.sampling_mode,
+            SamplingMode::Budget,
+            "Point Budget",
+        );
+        ui.radio_value(
+            &mut self.sampling_mode,
+            SamplingMode::VoxelGrid,
+            "Voxel Grid",
+        );
+        ui.radio_value(&mut self.sampling_mode, SamplingMode::Weighted, "Weighted");
+
+        ui.columns(2, |columns| {
+            columns[0].label("Step");
             columns[1].text_edit_singleline(&mut self.sampling_string);
         });
 
+        ui.columns(2, |columns| {
+            columns[0].label("Budget");
+            columns[1].text_edit_singleline(&mut self.budget_string);
+        });
+
+        ui.columns(2, |columns| {
+            columns[0].label("Voxel (m)");
+            columns[1].text_edit_singleline(&mut self.voxel_size_string);
+        });
+
+        ui.columns(2, |columns| {
+            columns[0].label(format!("Keep ≥ ({})", self.moment.units()));
+            columns[1].text_edit_singleline(&mut self.keep_above_string);
+        });
+
         ui.add_space(10.0);
 
         ui.label("Cluster Mode");
         ui.radio_value(&mut self.clustering_mode, KNN, "KNN");
         ui.radio_value(&mut self.clustering_mode, DBSCAN, "DBSCAN");
+        ui.radio_value(&mut self.clustering_mode, ClusteringMode::None, "None");
 
         ui.add_space(10.0);
 
-        ui.label("Cluster Threshold");
+        ui.label("Cluster Threshold (km)");
         ui.text_edit_singleline(&mut self.clustering_t_string);
 
         ui.add_space(10.0);
 
+        ui.columns(2, |columns| {
+            columns[0].label("Density Radius (m)");
+            columns[1].text_edit_singleline(&mut self.density_radius_string);
+        });
+
+        ui.add_space(10.0);
+
+        ui.checkbox(&mut self.grid_enabled, "Grid Volume");
+        if self.grid_enabled {
+            ui.radio_value(
+                &mut self.grid_interpolation,
+                GridInterpolation::Nearest,
+                "Nearest",
+            );
+            ui.radio_value(
+                &mut self.grid_interpolation,
+                GridInterpolation::Cressman,
+                "Cressman",
+            );
+            ui.radio_value(
+                &mut self.grid_interpolation,
+                GridInterpolation::Barnes,
+                "Barnes",
+            );
+
+            ui.columns(2, |columns| {
+                columns[0].label("Extent (km)");
+                columns[1].text_edit_singleline(&mut self.grid_extent_string);
+            });
+
+            ui.columns(2, |columns| {
+                columns[0].label("Top (km)");
+                columns[1].text_edit_singleline(&mut self.grid_top_string);
+            });
+
+            ui.columns(2, |columns| {
+                columns[0].label("Horiz. Res. (m)");
+                columns[1].text_edit_singleline(&mut self.grid_resolution_string);
+            });
+
+            ui.columns(2, |columns| {
+                columns[0].label("Vert. Res. (m)");
+                columns[1].text_edit_singleline(&mut self.grid_vertical_string);
+            });
+        }
+
+        ui.add_space(10.0);
+    }
+
+    fn update_apply(&mut self, ui: &mut Ui) -> Option<DataParams> {
+        ui.add_space(10.0);
+
         let apply_button = ui.button("Apply");
 
+        if let Some(ref input_error) = self.input_error {
+            ui.colored_label(Color32::from_rgb(255, 0, 0), input_error);
+        }
+
         ui.add_space(10.0);
 
         if apply_button.clicked() {
-            return Some(DataParams {
-                site: self.site_string.clone(),
-                date: NaiveDate::from_str(&self.date_string).unwrap(),
-                time: NaiveTime::from_str(&self.time_string).unwrap(),
-                sampling: u16::from_str(&self.sampling_string).unwrap(),
-                clustering_mode: self.clustering_mode,
-                clustering_threshold: f32::from_str(&self.clustering_t_string).unwrap(),
-            });
+            match self.parse_data_params() {
+                Ok(data_params) => {
+                    self.input_error = None;
+                    return Some(data_params);
+                }
+                Err(input_error) => self.input_error = Some(input_error),
+            }
         }
 
         None
     }
 
+    fn parse_data_params(&self) -> Result<DataParams, String> {
+        Ok(DataParams {
+            site: parse_site(&self.site_string)?,
+            date: parse_date(self.date_string.trim())?,
+            time: parse_time(self.time_string.trim())?,
+            scan_selection: self.scan_selection,
+            scan_tolerance: parse_tolerance(&self.tolerance_string)?,
+            sweep_selection: self.sweep_selection,
+            moment: self.moment,
+            threshold: parse_value(&self.threshold_string)?,
+            refraction_factor: parse_positive(&self.refraction_string)?,
+            sampling_mode: self.sampling_mode,
+            sampling: parse_sampling(&self.sampling_string)?,
+            point_budget: parse_budget(&self.budget_string)?,
+            voxel_size_m: parse_positive(&self.voxel_size_string)?,
+            keep_above: parse_value(&self.keep_above_string)?,
+            clustering_mode: self.clustering_mode,
+            clustering_threshold: parse_positive(&self.clustering_t_string)?,
+            density_radius_m: parse_positive(&self.density_radius_string)?,
+            grid: if self.grid_enabled {
+                let grid = GridParams {
+                    interpolation: self.grid_interpolation,
+                    extent_km: parse_positive(&self.grid_extent_string)?,
+                    top_km: parse_positive(&self.grid_top_string)?,
+                    horizontal_resolution_m: parse_positive(&self.grid_resolution_string)?,
+                    vertical_resolution_m: parse_positive(&self.grid_vertical_string)?,
+                };
+                grid.check_size()?;
+                Some(grid)
+            } else {
+                None
+            },
+            source: self.source.clone(),
+        })
+    }
+
+    fn update_cache(&self, ui: &mut Ui, cache: &Mutex<Cache>) {
+        ui.collapsing("Cache", |ui| {
+            // Processing holds the cache while reading and writing volumes, so don't stall the
+            // frame waiting on it
+            let Ok(mut cache) = cache.try_lock() else {
+                ui.colored_label(Color32::from_rgb(180, 180, 180), "Cache in use...");
+                return;
+            };
+
+            ui.columns(2, |columns| {
+                columns[0].label("Volumes");
+                columns[1].colored_label(
+                    Color32::from_rgb(255, 255, 255),
+                    cache.entries().len().to_string(),
+                );
+
+                columns[0].label("Size");
+                columns[1].colored_label(
+                    Color32::from_rgb(255, 255, 255),
+                    format!(
+                        "{} / {} MB",
+                        cache.total_bytes() / (1024 * 1024),
+                        cache.max_bytes() / (1024 * 1024),
+                    ),
+                );
+            });
+
+            for entry in cache.entries() {
+                ui.label(format!(
+                    "{} ({} MB)",
+                    entry.identifier,
+                    entry.size / (1024 * 1024)
+                ));
+            }
+
+            if ui.button("Clear Cache").clicked() {
+                if let Err(err) = cache.clear() {
+                    println!("Failed to clear cache: {}", err);
+                }
+            }
+        });
+
+        ui.add_space(10.0);
+    }
+
     fn update_status(&self, ui: &mut Ui, state: &State) {
-        ui.with_layout(Layout::bottom_up(Align::Center), |ui| {
-            ui.add_space(10.0);
+        ui.vertical_centered(|ui| {
+            if state.processing {
+                ui.colored_label(Color32::from_rgb(255, 0, 0), "Processing data...");
+            }
+
+            if let Some(ref error) = state.error {
+                ui.colored_label(
+                    Color32::from_rgb(255, 0, 0),
+                    format!("{:?} failed: {}", error.stage, error.error),
+                );
+            }
 
             if let Some(ref stats) = state.statistics {
                 ui.label(format!(
                     "Load {}, Decompress {}, Decode {}, \
-                    Pointing {}, Sampling {}, Coloring {}",
+                    Pointing {}, Gridding {}, Sampling {}, Clustering {}, Coloring {}",
                     stats.load_ms,
                     stats.decompress_ms,
                     stats.decode_ms,
                     stats.pointing_ms,
+                    stats.gridding_ms,
                     stats.sampling_ms,
+                    stats.clustering_ms,
                     stats.coloring_ms,
                 ));
             }
 
-            if state.processing {
-                ui.colored_label(Color32::from_rgb(255, 0, 0), "Processing data...");
-            }
+            ui.add_space(10.0);
         });
     }
 
@@ -223,3 +1122,19 @@ impl Gui {
             .write(|| self.gui.as_ref().unwrap().render());
     }
 }
+
+fn optional_label(value: Option<impl ToString>) -> String {
+    value.map_or("-".to_string(), |value| value.to_string())
+}
+
+fn source_label(source: &DataSource) -> String {
+    match source {
+        DataSource::Remote => "Download".to_string(),
+        DataSource::Local(path) => path
+            .file_name()
+            .map(Path::new)
+            .unwrap_or(path)
+            .display()
+            .to_string(),
+    }
+}
@@ -0,0 +1,160 @@
+use crate::beam::render_position;
+use crate::grid::Grid;
+use std::collections::HashMap;
+use three_d::{CpuMesh, Indices, InnerSpace, Positions, Vector3};
+
+/// The six tetrahedra a grid cube is split into, all sharing the diagonal from corner 0 to corner
+/// 7. Corner `i` is offset by `(i & 1, i >> 1 & 1, i >> 2 & 1)` cells along x, y, and z, so the
+/// split is the same in every cube and neighboring cubes' faces line up.
+const CUBE_TETRAHEDRA: [[usize; 4]; 6] = [
+    [0, 1, 3, 7],
+    [0, 3, 2, 7],
+    [0, 2, 6, 7],
+    [0, 6, 4, 7],
+    [0, 4, 5, 7],
+    [0, 5, 1, 7],
+];
+
+/// Extracts the surface where the grid crosses the level as a render-space mesh with smooth
+/// normals, or `None` if no cell crosses it. Marches each cube of neighboring cell centers as six
+/// tetrahedra, which unlike the classic cube case table has no ambiguous cases, so the surface is
+/// closed wherever the data is. Cells without data count as below the level.
+pub fn isosurface_mesh(grid: &Grid, level: f32) -> Option<CpuMesh> {
+    let [width, height, depth] = grid.dimensions;
+    if width < 2 || height < 2 || depth < 2 {
+        return None;
+    }
+
+    let mut surface = Surface {
+        grid,
+        level,
+        positions: Vec::new(),
+        indices: Vec::new(),
+        vertices: HashMap::new(),
+    };
+
+    for z in 0..depth - 1 {
+        for y in 0..height - 1 {
+            for x in 0..width - 1 {
+                let corners = [0, 1, 2, 3, 4, 5, 6, 7]
+                    .map(|corner| [x + (corner & 1), y + (corner >> 1 & 1), z + (corner >> 2)]);
+
+                let inside = corners.map(|cell| grid.value(cell) >= level);
+                if inside.iter().all(|&inside| inside) || inside.iter().all(|&inside| !inside) {
+                    continue;
+                }
+
+                for tetrahedron in CUBE_TETRAHEDRA {
+                    surface.march_tetrahedron(tetrahedron.map(|corner| corners[corner]));
+                }
+            }
+        }
+    }
+
+    if surface.indices.is_empty() {
+        return None;
+    }
+
+    let positions = surface
+        .positions
+        .iter()
+        .map(|position| {
+            render_position(
+                position.z.atan2(position.x),
+                position.x.hypot(position.z),
+                position.y,
+            )
+        })
+        .collect();
+
+    let mut mesh = CpuMesh {
+        positions: Positions::F32(positions),
+        indices: Indices::U32(surface.indices),
+        ..Default::default()
+    };
+    mesh.compute_normals();
+
+    Some(mesh)
+}
+
+/// A surface being extracted, in grid meters. Vertices are shared between the triangles on each
+/// grid edge so normals are smoothed across them.
+struct Surface<'a> {
+    grid: &'a Grid,
+    level: f32,
+    positions: Vec<Vector3<f32>>,
+    indices: Vec<u32>,
+    vertices: HashMap<([usize; 3], [usize; 3]), u32>,
+}
+
+impl Surface<'_> {
+    /// Adds the triangles where the surface crosses a tetrahedron of cells, wound so their normals
+    /// point toward the values below the level.
+    fn march_tetrahedron(&mut self, cells: [[usize; 3]; 4]) {
+        let (inside, outside): (Vec<_>, Vec<_>) = cells
+            .into_iter()
+            .partition(|&cell| self.grid.value(cell) >= self.level);
+
+        let mean = |cells: &[[usize; 3]]| {
+            cells
+                .iter()
+                .map(|&cell| self.grid.cell_center(cell))
+                .sum::<Vector3<f32>>()
+                / cells.len() as f32
+        };
+        let outward = mean(&outside) - mean(&inside);
+
+        match (inside.as_slice(), outside.as_slice()) {
+            ([lone], others) | (others, [lone]) if others.len() == 3 => {
+                let triangle = [0, 1, 2].map(|other| self.vertex(*lone, others[other]));
+                self.add_triangle(triangle, outward);
+            }
+            ([a, b], [c, d]) => {
+                let quad = [
+                    self.vertex(*a, *c),
+                    self.vertex(*a, *d),
+                    self.vertex(*b, *d),
+                    self.vertex(*b, *c),
+                ];
+                self.add_triangle([quad[0], quad[1], quad[2]], outward);
+                self.add_triangle([quad[0], quad[2], quad[3]], outward);
+            }
+            _ => {}
+        }
+    }
+
+    /// The vertex where the surface crosses the edge between two cells, interpolated linearly
+    /// between their values or at the midpoint if either has no data.
+    fn vertex(&mut self, from: [usize; 3], to: [usize; 3]) -> u32 {
+        let key = if from < to { (from, to) } else { (to, from) };
+        if let Some(&vertex) = self.vertices.get(&key) {
+            return vertex;
+        }
+
+        let (from_value, to_value) = (self.grid.value(key.0), self.grid.value(key.1));
+        let fraction = if from_value.is_finite() && to_value.is_finite() && from_value != to_value {
+            ((self.level - from_value) / (to_value - from_value)).clamp(0.0, 1.0)
+        } else {
+            0.5
+        };
+
+        let (from_center, to_center) = (self.grid.cell_center(key.0), self.grid.cell_center(key.1));
+        self.positions
+            .push(from_center + (to_center - from_center) * fraction);
+
+        let vertex = self.positions.len() as u32 - 1;
+        self.vertices.insert(key, vertex);
+        vertex
+    }
+
+    fn add_triangle(&mut self, [a, b, c]: [u32; 3], outward: Vector3<f32>) {
+        let position = |vertex: u32| self.positions[vertex as usize];
+        let normal = (position(b) - position(a)).cross(position(c) - position(a));
+
+        if normal.dot(outward) < 0.0 {
+            self.indices.extend([a, c, b]);
+        } else {
+            self.indices.extend([a, b, c]);
+        }
+    }
+}
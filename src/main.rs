@@ -1,45 +1,113 @@
-use crate::data::ColoredPoint;
+use crate::bench::benchmark_pointing;
+use crate::cache::Cache;
+use crate::cli::{print_cache, Args};
+use crate::color_table::ColorTable;
+use crate::data::{get_data, ColoredPoint};
 use crate::gui::Gui;
-use crate::object::{get_earth_object, get_point_cloud_object, get_radar_indicator_object};
-use crate::param::{ClusteringMode, DataParams, InteractionMode, PointColorMode, VisParams};
-use crate::processing::do_fetch_and_process;
-use chrono::{NaiveDate, NaiveTime};
+use crate::object::{
+    get_direct_volume_object, get_earth_object, get_gates_object, get_isosurface_objects,
+    get_radar_indicator_object,
+};
+use crate::param::{DataParams, InteractionMode, VisParams};
+use crate::processing::{color_points, do_fetch_and_process};
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser};
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
-use std::thread::current;
 use three_d::{
-    ClearState, ColorMaterial, FrameOutput, Gm, InstancedMesh, Viewport, Window, WindowSettings,
+    ClearState, FrameOutput, Gm, Mesh, Object, PhysicalMaterial, Viewport, Window, WindowSettings,
 };
+use tokio_util::sync::CancellationToken;
 
 use crate::result::Result;
 use crate::scene::{do_auto_orbit, get_camera_and_control, get_sun_light};
-use crate::state::State;
+use crate::state::{ProcessingStage, ProcessingStatistics, State};
+use crate::volume::VolumeMaterial;
 
+mod beam;
+mod bench;
+mod cache;
+mod cli;
+mod color_table;
 mod data;
+mod grid;
 mod gui;
+mod isosurface;
 mod object;
 mod param;
 mod processing;
 mod result;
+mod sampling;
 mod scene;
+mod spatial;
 mod state;
-
-const TARGET_SITE: &str = "KDMX";
+mod transfer;
+mod volume;
 
 #[tokio::main]
 async fn main() {
-    let target_date = NaiveDate::from_ymd_opt(2022, 3, 5).unwrap();
-    let target_time = NaiveTime::from_hms_opt(23, 30, 0).unwrap();
+    let args = Args::parse();
+    let vis_params = match args.check().and_then(|()| args.vis_params()) {
+        Ok(vis_params) => vis_params,
+        Err(message) => Args::command()
+            .error(ErrorKind::ValueValidation, message)
+            .exit(),
+    };
+
+    if let Err(err) = run(&args, vis_params).await {
+        eprintln!("Error: {}", err);
+        std::process::exit(1);
+    }
+}
+
+async fn run(args: &Args, vis_params: VisParams) -> Result<()> {
+    let mut cache = Cache::open(&args.cache_dir, args.cache_max_bytes())?;
+
+    if args.clear_cache {
+        cache.clear()?;
+        println!("Cleared cache {}.", cache.dir().display());
+        return Ok(());
+    }
+
+    if args.list_cache {
+        print_cache(&cache);
+        return Ok(());
+    }
 
-    execute(TARGET_SITE, &target_date, &target_time)
-        .await
-        .unwrap();
+    if let Some(iterations) = args.benchmark_pointing {
+        let data = get_data(
+            &args.data_params(),
+            &Mutex::new(cache),
+            &CancellationToken::new(),
+            &mut ProcessingStage::Load,
+            &mut ProcessingStatistics::default(),
+        )
+        .await?;
+
+        return benchmark_pointing(&data, &args.data_params(), iterations);
+    }
+
+    execute(
+        args.data_params(),
+        vis_params,
+        &args.files,
+        &args.color_tables,
+        Arc::new(Mutex::new(cache)),
+    )
+    .await
 }
 
 const RENDER_RATIO_TO_M: f32 = 0.00001; // every 1.0 in the render == 1.0/RENDER_RATIO_TO_M meters
 
 const CONTROL_PANEL_WIDTH: f32 = 200.0;
 
-async fn execute(site: &str, date: &NaiveDate, time: &NaiveTime) -> Result<()> {
+async fn execute(
+    mut data_params: DataParams,
+    mut vis_params: VisParams,
+    local_files: &[PathBuf],
+    color_tables: &[ColorTable],
+    cache: Arc<Mutex<Cache>>,
+) -> Result<()> {
     let window = Window::new(WindowSettings {
         title: "NEXRAD Volumetric Renderer".to_string(),
         ..Default::default()
@@ -53,32 +121,30 @@ async fn execute(site: &str, date: &NaiveDate, time: &NaiveTime) -> Result<()> {
 
     let state = Arc::new(Mutex::new(State {
         processing: false,
+        job: None,
         points: None,
+        metadata: None,
+        grid: None,
         statistics: None,
+        error: None,
     }));
 
-    let mut data_params = DataParams {
-        site: site.to_string(),
-        date: *date,
-        time: *time,
-        sampling: 10000,
-        clustering_mode: ClusteringMode::DBSCAN,
-        clustering_threshold: 10.0,
-    };
-
-    do_fetch_and_process(data_params.clone(), state.clone());
-
-    let mut vis_params = VisParams {
-        interaction_mode: InteractionMode::ManualOrbit,
-        point_color_mode: PointColorMode::Raw,
-    };
+    do_fetch_and_process(data_params.clone(), state.clone(), cache.clone());
 
     let (mut camera, mut control) = get_camera_and_control(&window);
-    let mut gui = Gui::new(&context, &data_params);
+    let mut gui = Gui::new(
+        &context,
+        &data_params,
+        local_files,
+        color_tables,
+        &vis_params.isosurface_levels,
+    );
 
     let mut angle_deg = 0.0;
 
-    let mut point_cloud: Option<Gm<InstancedMesh, ColorMaterial>> = None;
+    let mut gates: Option<Box<dyn Object>> = None;
+    let mut isosurfaces: Option<Vec<Gm<Mesh, PhysicalMaterial>>> = None;
+    let mut volume: Option<Gm<Mesh, VolumeMaterial>> = None;
 
     window.render_loop(move |mut frame_input| {
         let scaled_width = CONTROL_PANEL_WIDTH * frame_input.device_pixel_ratio;
@@ -95,49 +161,114 @@ async fn execute(site: &str, date: &NaiveDate, time: &NaiveTime) -> Result<()> {
             do_auto_orbit(&mut angle_deg, &mut camera);
         }
 
-        {
+        let new_data_params = {
             let mut current_state = state.lock().unwrap();
-            let (new_vis_params, new_data_params) =
-                gui.update(&mut frame_input, &current_state, &vis_params, &data_params);
+            let (new_vis_params, new_data_params) = gui.update(
+                &mut frame_input,
+                &current_state,
+                &cache,
+                &vis_params,
+                &data_params,
+            );
 
             if let Some(new_vis_params) = new_vis_params {
+                if new_vis_params.point_color_mode != vis_params.point_color_mode
+                    || new_vis_params.gate_render_mode != vis_params.gate_render_mode
+                    || new_vis_params.show_range_folded != vis_params.show_range_folded
+                    || new_vis_params.density_colormap != vis_params.density_colormap
+                    || new_vis_params.color_table != vis_params.color_table
+                {
+                    gates = None;
+                }
+                if new_vis_params.isosurface_levels != vis_params.isosurface_levels
+                    || new_vis_params.color_table != vis_params.color_table
+                {
+                    isosurfaces = None;
+                }
                 vis_params = new_vis_params;
-                point_cloud = None;
             }
 
-            if let Some(new_data_params) = new_data_params {
-                data_params = new_data_params;
-                point_cloud = None;
+            if new_data_params.is_some() {
+                gates = None;
+                isosurfaces = None;
+                volume = None;
                 current_state.points = None;
+                current_state.metadata = None;
+                current_state.grid = None;
                 current_state.statistics = None;
-                do_fetch_and_process(data_params.clone(), state.clone());
             }
+
+            new_data_params
+        };
+
+        if let Some(new_data_params) = new_data_params {
+            data_params = new_data_params;
+            do_fetch_and_process(data_params.clone(), state.clone(), cache.clone());
         }
 
         let objects = earth.into_iter().chain(&radar_indicator);
 
-        if point_cloud.is_none() {
-            {
-                let mut state = state.lock().unwrap();
-                if !state.processing && state.points.is_some() {
-                    println!("Regenerating point cloud...");
-                    let points = state.points.as_ref().unwrap().clone();
-                    point_cloud = Some(get_point_cloud_object(&context, &vis_params, points));
-                }
+        // Without a grid there's no volume to ray-march, so the gates are drawn instead
+        let draw_volume = vis_params.direct_volume && state.lock().unwrap().grid.is_some();
+
+        if !draw_volume && gates.is_none() {
+            let state = state.lock().unwrap();
+            if let (false, Some(points)) = (state.processing, state.points.as_ref()) {
+                println!("Regenerating gates...");
+                let mut points = points.clone();
+                color_points(
+                    &mut points,
+                    &vis_params.color_table,
+                    vis_params.density_colormap,
+                );
+                gates = Some(get_gates_object(
+                    &context,
+                    &vis_params,
+                    data_params.refraction_factor,
+                    points,
+                ));
             }
         }
 
-        if let Some(ref point_cloud) = point_cloud {
-            frame_input
-                .screen()
-                .clear(ClearState::color_and_depth(0.0, 0.0, 0.0, 1.0, 1.0))
-                .render(&camera, objects.chain(point_cloud), &[&sun]);
-        } else {
-            frame_input
-                .screen()
-                .clear(ClearState::color_and_depth(0.0, 0.0, 0.0, 1.0, 1.0))
-                .render(&camera, objects, &[&sun]);
-        };
+        if isosurfaces.is_none() {
+            let state = state.lock().unwrap();
+            if let (false, Some(grid)) = (state.processing, state.grid.as_ref()) {
+                println!("Regenerating isosurfaces...");
+                isosurfaces = Some(get_isosurface_objects(
+                    &context,
+                    grid,
+                    &vis_params.isosurface_levels,
+                    &vis_params.color_table,
+                ));
+            }
+        }
+
+        if draw_volume && volume.is_none() {
+            let state = state.lock().unwrap();
+            if let (false, Some(grid)) = (state.processing, state.grid.as_ref()) {
+                println!("Regenerating volume...");
+                volume = Some(get_direct_volume_object(&context, &vis_params, grid));
+            }
+        }
+
+        if let Some(volume) = volume.as_mut() {
+            volume.material.update(&vis_params);
+        }
+
+        let gates_object = gates.as_deref().filter(|_| !draw_volume);
+        let volume_object = volume.as_ref().filter(|_| draw_volume);
+
+        frame_input
+            .screen()
+            .clear(ClearState::color_and_depth(0.0, 0.0, 0.0, 1.0, 1.0))
+            .render(
+                &camera,
+                objects
+                    .chain(gates_object)
+                    .chain(isosurfaces.iter().flatten().flatten())
+                    .chain(volume_object.into_iter().flatten()),
+                &[&sun],
+            );
 
         gui.render(&frame_input);
         FrameOutput::default()
@@ -1,11 +1,18 @@
-use crate::param::{PointColorMode, VisParams};
+use crate::beam::{beam_height_m, ground_range_m, render_position};
+use crate::color_table::ColorTable;
+use crate::data::GateGeometry;
+use crate::grid::Grid;
+use crate::isosurface::isosurface_mesh;
+use crate::param::{GateRenderMode, PointColorMode, VisParams};
+use crate::volume::VolumeMaterial;
 use crate::{ColoredPoint, RENDER_RATIO_TO_M};
+use rayon::prelude::*;
 use three_d::{
-    degrees, vec3, ColorMaterial, Context, CpuMaterial, CpuMesh, Gm, InstancedMesh, Mat4, Mesh,
-    PhysicalMaterial, PointCloud, Positions, Srgba,
+    degrees, vec3, ColorMaterial, Context, CpuMaterial, CpuMesh, Cull, Gm, Indices, InstancedMesh,
+    Mat4, Mesh, Object, PhysicalMaterial, PointCloud, Positions, Srgba, Vec3,
 };
 
-const EARTH_RADIUS_M: f32 = 6356752.3;
+pub const EARTH_RADIUS_M: f32 = 6356752.3;
 pub const NEXRAD_RADAR_RANGE_M: f32 = 230000.0;
 
 pub fn get_earth_object(context: &Context) -> Gm<Mesh, PhysicalMaterial> {
@@ -67,40 +74,185 @@ pub fn get_radar_indicator_object(context: &Context) -> Gm<Mesh, PhysicalMateria
     radar_indicator
 }
 
-pub fn get_point_cloud_object(
+/// Opacity of isosurfaces, low enough that surfaces at higher levels show through those enclosing
+/// them.
+const ISOSURFACE_ALPHA: u8 = 90;
+
+/// Builds a translucent surface for each level the grid crosses, colored per the color table.
+pub fn get_isosurface_objects(
+    context: &Context,
+    grid: &Grid,
+    levels: &[f32],
+    table: &ColorTable,
+) -> Vec<Gm<Mesh, PhysicalMaterial>> {
+    let meshes = levels
+        .par_iter()
+        .filter_map(|&level| isosurface_mesh(grid, level).map(|mesh| (level, mesh)))
+        .collect::<Vec<_>>();
+
+    meshes
+        .into_iter()
+        .map(|(level, mesh)| {
+            let color = table.rgb(level);
+
+            let mut material = PhysicalMaterial::new_transparent(
+                context,
+                &CpuMaterial {
+                    albedo: Srgba::new(color.0, color.1, color.2, ISOSURFACE_ALPHA),
+                    ..Default::default()
+                },
+            );
+            material.render_states.cull = Cull::None;
+
+            Gm::new(Mesh::new(context, &mesh), material)
+        })
+        .collect()
+}
+
+/// Builds the ray-marched volume of the grid, initially per the visualization parameters.
+pub fn get_direct_volume_object(
     context: &Context,
     vis_params: &VisParams,
+    grid: &Grid,
+) -> Gm<Mesh, VolumeMaterial> {
+    let material = VolumeMaterial::new(context, grid, vis_params);
+    let (min, max) = material.render_bounds();
+
+    let mut volume = Gm::new(Mesh::new(context, &CpuMesh::cube()), material);
+    volume.set_transformation(
+        Mat4::from_translation((min + max) / 2.0)
+            * Mat4::from_nonuniform_scale(
+                (max.x - min.x) / 2.0,
+                (max.y - min.y) / 2.0,
+                (max.z - min.z) / 2.0,
+            ),
+    );
+
+    volume
+}
+
+/// Builds the object drawing the specified gates per the visualization parameters' render mode.
+pub fn get_gates_object(
+    context: &Context,
+    vis_params: &VisParams,
+    refraction_factor: f32,
     points: Vec<ColoredPoint>,
+) -> Box<dyn Object> {
+    let points = points
+        .into_iter()
+        .filter(|p| vis_params.show_range_folded || !p.folded)
+        .collect::<Vec<_>>();
+
+    match vis_params.gate_render_mode {
+        GateRenderMode::Points => Box::new(get_point_cloud_object(context, vis_params, &points)),
+        GateRenderMode::Volumes => Box::new(get_gate_volume_object(
+            context,
+            vis_params,
+            refraction_factor,
+            &points,
+        )),
+    }
+}
+
+fn get_point_cloud_object(
+    context: &Context,
+    vis_params: &VisParams,
+    points: &[ColoredPoint],
 ) -> Gm<InstancedMesh, ColorMaterial> {
-    let mut point_cloud = PointCloud::default();
-    point_cloud.positions = Positions::F32(
-        points
-            .iter()
-            .map(|p| vec3(p.pos.x, p.pos.y, p.pos.z))
-            .collect::<Vec<_>>(),
-    );
-    point_cloud.colors = Some(
-        points
-            .iter()
-            .map(|p| {
-                let color = match vis_params.point_color_mode {
-                    PointColorMode::Raw => p.raw,
-                    PointColorMode::Density => p.density,
-                    PointColorMode::Hybrid => p.hybrid,
-                };
-
-                Srgba::new(color.0, color.1, color.2, 255)
-            })
-            .collect::<Vec<_>>(),
-    );
+    let point_cloud = PointCloud {
+        positions: Positions::F32(
+            points
+                .iter()
+                .map(|p| vec3(p.pos.x, p.pos.y, p.pos.z))
+                .collect::<Vec<_>>(),
+        ),
+        colors: Some(
+            points
+                .iter()
+                .map(|p| point_color(p, vis_params.point_color_mode))
+                .collect::<Vec<_>>(),
+        ),
+    };
 
     let mut point_mesh = CpuMesh::sphere(4);
     point_mesh.transform(&Mat4::from_scale(0.002)).unwrap();
 
-    let point_cloud_gm = Gm {
-        geometry: InstancedMesh::new(&context, &point_cloud.into(), &point_mesh),
+    Gm {
+        geometry: InstancedMesh::new(context, &point_cloud.into(), &point_mesh),
         material: ColorMaterial::default(),
+    }
+}
+
+/// Quads of a gate volume's corners, indexed by (azimuth, range, elevation) bits per
+/// `gate_corners`.
+const GATE_VOLUME_FACES: [[u32; 4]; 6] = [
+    [0, 1, 3, 2],
+    [4, 5, 7, 6],
+    [0, 1, 5, 4],
+    [2, 3, 7, 6],
+    [0, 2, 6, 4],
+    [1, 3, 7, 5],
+];
+
+/// Draws each gate as its sample volume so adjacent gates form a contiguous volume, growing with
+/// range as the beam spreads.
+fn get_gate_volume_object(
+    context: &Context,
+    vis_params: &VisParams,
+    refraction_factor: f32,
+    points: &[ColoredPoint],
+) -> Gm<Mesh, ColorMaterial> {
+    let mut positions = Vec::with_capacity(points.len() * 8);
+    let mut colors = Vec::with_capacity(points.len() * 8);
+    let mut indices = Vec::with_capacity(points.len() * GATE_VOLUME_FACES.len() * 6);
+
+    for point in points {
+        let first_corner = positions.len() as u32;
+        positions.extend(gate_corners(&point.gate, refraction_factor));
+        colors.extend([point_color(point, vis_params.point_color_mode); 8]);
+
+        for [a, b, c, d] in GATE_VOLUME_FACES {
+            indices.extend([a, b, c, a, c, d].map(|corner| first_corner + corner));
+        }
+    }
+
+    let mesh = CpuMesh {
+        positions: Positions::F32(positions),
+        indices: Indices::U32(indices),
+        colors: Some(colors),
+        ..Default::default()
+    };
+
+    Gm {
+        geometry: Mesh::new(context, &mesh),
+        material: ColorMaterial::default(),
+    }
+}
+
+/// Render positions of a gate volume's eight corners, the corner at index `(a << 2) | (r << 1) | e`
+/// being at the far azimuth/range/elevation edge for each set bit.
+fn gate_corners(gate: &GateGeometry, refraction_factor: f32) -> [Vec3; 8] {
+    [0, 1, 2, 3, 4, 5, 6, 7].map(|corner| {
+        let edge = |bit: usize| if corner & (1 << bit) == 0 { -0.5 } else { 0.5 };
+
+        let azimuth_rad = gate.azimuth_rad + edge(2) * gate.azimuth_width_rad;
+        let range_m = gate.range_m + edge(1) * gate.gate_length_m;
+        let elevation_rad = gate.elevation_rad + edge(0) * gate.beam_width_rad;
+
+        let height_m = beam_height_m(range_m, elevation_rad, refraction_factor);
+        let ground_range_m = ground_range_m(range_m, elevation_rad, height_m, refraction_factor);
+
+        render_position(azimuth_rad, ground_range_m, height_m)
+    })
+}
+
+fn point_color(point: &ColoredPoint, mode: PointColorMode) -> Srgba {
+    let color = match mode {
+        PointColorMode::Raw => point.raw,
+        PointColorMode::Density => point.density,
+        PointColorMode::Hybrid => point.hybrid,
+        PointColorMode::Cluster => point.cluster,
     };
 
-    point_cloud_gm
+    Srgba::new(color.0, color.1, color.2, 255)
 }
@@ -1,28 +1,362 @@
+use crate::color_table::ColorTable;
+use crate::transfer::TransferFunction;
 use chrono::{NaiveDate, NaiveTime};
+use clap::ValueEnum;
+use std::path::PathBuf;
 
-#[derive(Eq, PartialEq, Copy, Clone)]
+#[derive(Eq, PartialEq, Copy, Clone, Debug, ValueEnum)]
 pub enum InteractionMode {
     Orbit,
     ManualOrbit,
 }
 
-#[derive(Eq, PartialEq, Copy, Clone)]
+#[derive(Eq, PartialEq, Copy, Clone, Debug, ValueEnum)]
 pub enum PointColorMode {
     Raw,
     Density,
     Hybrid,
+    Cluster,
+}
+
+/// How each gate is drawn.
+#[derive(Eq, PartialEq, Copy, Clone, Debug, ValueEnum)]
+pub enum GateRenderMode {
+    /// A small fixed-size sphere at the gate's center.
+    Points,
+    /// The gate's sample volume, azimuth width by gate length by beam width.
+    Volumes,
+}
+
+/// A starting point for the volume rendering transfer function.
+#[derive(Eq, PartialEq, Copy, Clone, Debug, ValueEnum)]
+pub enum TransferPreset {
+    /// The moment's color table, increasingly opaque toward strong values.
+    ColorTable,
+    /// The moment's color table, transparent but for the strongest half of the range.
+    Cores,
+    /// Black to white, increasingly opaque toward strong values.
+    Grayscale,
+}
+
+impl TransferPreset {
+    pub const ALL: [TransferPreset; 3] = [
+        TransferPreset::ColorTable,
+        TransferPreset::Cores,
+        TransferPreset::Grayscale,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            TransferPreset::ColorTable => "Color Table",
+            TransferPreset::Cores => "Cores",
+            TransferPreset::Grayscale => "Grayscale",
+        }
+    }
 }
 
 #[derive(PartialEq, Clone)]
 pub struct VisParams {
     pub interaction_mode: InteractionMode,
     pub point_color_mode: PointColorMode,
+    pub gate_render_mode: GateRenderMode,
+    pub show_range_folded: bool,
+    pub density_colormap: DensityColormap,
+    /// Colors for the current moment's values, used for points, isosurfaces, and the color table
+    /// transfer function presets.
+    pub color_table: ColorTable,
+    /// Values the gridded moment is drawn as isosurfaces at, in ascending order.
+    pub isosurface_levels: Vec<f32>,
+    /// Ray-march the gridded moment through a color and opacity transfer function instead of
+    /// drawing the gates.
+    pub direct_volume: bool,
+    /// Distance between samples along each ray in direct volume rendering.
+    pub volume_step_m: f32,
+    /// Multiplier applied to the transfer function's opacities in direct volume rendering.
+    pub volume_opacity_scale: f32,
+    /// Color and opacity by value in direct volume rendering, in the current moment's units.
+    pub transfer_function: TransferFunction,
+    /// Preset the transfer function starts over from when the moment changes.
+    pub transfer_preset: TransferPreset,
 }
 
-#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Eq, PartialEq, Copy, Clone, Debug, ValueEnum)]
 pub enum ClusteringMode {
     KNN,
     DBSCAN,
+    /// No clustering, leaving every point unclustered, e.g. to skip its cost on dense volumes.
+    None,
+}
+
+/// How the volume's points are reduced before clustering and rendering.
+#[derive(Eq, PartialEq, Copy, Clone, Debug, ValueEnum)]
+pub enum SamplingMode {
+    /// Every Nth gate.
+    Step,
+    /// A uniform random sample of the point budget.
+    Budget,
+    /// The strongest gate in each cube of the voxel size.
+    VoxelGrid,
+    /// Every gate at or above the keep value plus a sample weighted toward stronger gates, up to
+    /// the point budget.
+    Weighted,
+}
+
+/// How gate values are weighted when interpolated onto a Cartesian grid.
+#[derive(Eq, PartialEq, Copy, Clone, Debug, ValueEnum)]
+pub enum GridInterpolation {
+    /// The nearest gate's value.
+    Nearest,
+    /// Cressman weighting, (R² - d²) / (R² + d²).
+    Cressman,
+    /// Barnes (Gaussian) weighting.
+    Barnes,
+}
+
+/// Most cells a grid may have, bounding gridding's memory and the direct volume renderer's texture.
+pub const MAX_GRID_CELLS: usize = 1 << 24;
+
+/// Most cells along any one axis of a grid, the 3D texture size GPUs commonly support.
+pub const MAX_GRID_DIMENSION: usize = 2048;
+
+/// The extent and resolution of a radar-centered Cartesian grid.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub struct GridParams {
+    pub interpolation: GridInterpolation,
+    /// Half the grid's width, its horizontal reach from the radar in each direction.
+    pub extent_km: f32,
+    /// Height of the grid's top above the surface.
+    pub top_km: f32,
+    pub horizontal_resolution_m: f32,
+    pub vertical_resolution_m: f32,
+}
+
+impl GridParams {
+    /// Cells along the x (east-west), y (vertical), and z (north-south) axes.
+    pub fn dimensions(&self) -> [usize; 3] {
+        // Float to integer casts saturate, so absurd sizes stay absurd rather than wrapping
+        let horizontal_count = (2.0 * self.extent_km as f64 * 1000.0
+            / self.horizontal_resolution_m as f64)
+            .ceil() as usize;
+        let vertical_count =
+            (self.top_km as f64 * 1000.0 / self.vertical_resolution_m as f64).ceil() as usize;
+
+        [horizontal_count, vertical_count, horizontal_count]
+    }
+
+    /// Checks the grid fits within the cell budget.
+    pub fn check_size(&self) -> Result<(), String> {
+        let dimensions = self.dimensions();
+        let cell_count = dimensions
+            .iter()
+            .try_fold(1usize, |count, dimension| count.checked_mul(*dimension));
+        let size = format!("{} x {} x {}", dimensions[0], dimensions[1], dimensions[2]);
+
+        if dimensions
+            .iter()
+            .any(|dimension| *dimension > MAX_GRID_DIMENSION)
+        {
+            Err(format!(
+                "a {} cell grid has over {} cells along an axis, coarsen the resolution or shrink it",
+                size, MAX_GRID_DIMENSION
+            ))
+        } else if cell_count.is_none_or(|count| count > MAX_GRID_CELLS) {
+            Err(format!(
+                "a {} cell grid has over {} cells, coarsen the resolution or shrink it",
+                size, MAX_GRID_CELLS
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Default for GridParams {
+    fn default() -> Self {
+        GridParams {
+            interpolation: GridInterpolation::Cressman,
+            extent_km: 150.0,
+            top_km: 15.0,
+            horizontal_resolution_m: 2000.0,
+            vertical_resolution_m: 1000.0,
+        }
+    }
+}
+
+/// Colormap applied to the normalized point density in the Density color mode.
+#[derive(Eq, PartialEq, Copy, Clone, Debug, ValueEnum)]
+pub enum DensityColormap {
+    Viridis,
+    Inferno,
+    Grayscale,
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub enum DataSource {
+    /// Find and download the scan nearest the requested site/date/time.
+    Remote,
+    /// Decode a Level II file already on disk, skipping any listing or download.
+    Local(PathBuf),
+}
+
+#[derive(Eq, PartialEq, Copy, Clone, Debug, ValueEnum)]
+pub enum ScanSelection {
+    /// The scan closest to the target time, before or after.
+    Nearest,
+    /// The latest scan at or before the target time.
+    AtOrBefore,
+    /// The earliest scan at or after the target time.
+    AtOrAfter,
+}
+
+/// Which sweeps are kept when a volume scans an elevation angle more than once, e.g. the
+/// surveillance and Doppler halves of a split cut or SAILS/MRLE repeated low-level sweeps.
+#[derive(Eq, PartialEq, Copy, Clone, Debug, ValueEnum)]
+pub enum SweepSelection {
+    /// The latest sweep at each elevation angle.
+    Latest,
+    /// Every sweep, overlapping where angles repeat.
+    All,
+    /// The latest sweep at each elevation angle which carries the selected moment.
+    ByMoment,
+}
+
+/// A radar moment (product) which can be rendered.
+#[derive(Eq, PartialEq, Copy, Clone, Debug, ValueEnum)]
+pub enum Moment {
+    Reflectivity,
+    Velocity,
+    SpectrumWidth,
+    DifferentialReflectivity,
+    CorrelationCoefficient,
+    DifferentialPhase,
+    /// Derived from differential phase's range derivative since it isn't in Level II data.
+    SpecificDifferentialPhase,
+}
+
+/// How a moment's threshold is compared against gate values.
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+pub enum ThresholdKind {
+    /// Gates with values above the threshold are kept.
+    Minimum,
+    /// Gates with magnitudes above the threshold are kept, e.g. for signed velocities.
+    Magnitude,
+}
+
+impl Moment {
+    pub const ALL: [Moment; 7] = [
+        Moment::Reflectivity,
+        Moment::Velocity,
+        Moment::SpectrumWidth,
+        Moment::DifferentialReflectivity,
+        Moment::CorrelationCoefficient,
+        Moment::DifferentialPhase,
+        Moment::SpecificDifferentialPhase,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Moment::Reflectivity => "Reflectivity",
+            Moment::Velocity => "Velocity",
+            Moment::SpectrumWidth => "Spectrum Width",
+            Moment::DifferentialReflectivity => "Diff. Reflectivity",
+            Moment::CorrelationCoefficient => "Correlation Coeff.",
+            Moment::DifferentialPhase => "Diff. Phase",
+            Moment::SpecificDifferentialPhase => "Specific Diff. Phase",
+        }
+    }
+
+    /// Abbreviation as in Level II data block names, e.g. REF.
+    pub fn abbreviation(&self) -> &'static str {
+        match self {
+            Moment::Reflectivity => "REF",
+            Moment::Velocity => "VEL",
+            Moment::SpectrumWidth => "SW",
+            Moment::DifferentialReflectivity => "ZDR",
+            Moment::CorrelationCoefficient => "RHO",
+            Moment::DifferentialPhase => "PHI",
+            Moment::SpecificDifferentialPhase => "KDP",
+        }
+    }
+
+    /// The moment a color table's product names, by its abbreviation or GRLevelX product code.
+    pub fn from_product(product: &str) -> Option<Moment> {
+        let product = product.trim().to_uppercase();
+        Moment::ALL
+            .into_iter()
+            .find(|moment| moment.abbreviation() == product)
+            .or(match product.as_str() {
+                "BR" | "DBZ" => Some(Moment::Reflectivity),
+                "BV" | "SRV" => Some(Moment::Velocity),
+                "DR" => Some(Moment::DifferentialReflectivity),
+                "CC" => Some(Moment::CorrelationCoefficient),
+                "DP" => Some(Moment::DifferentialPhase),
+                _ => None,
+            })
+    }
+
+    pub fn units(&self) -> &'static str {
+        match self {
+            Moment::Reflectivity => "dBZ",
+            Moment::Velocity | Moment::SpectrumWidth => "m/s",
+            Moment::DifferentialReflectivity => "dB",
+            Moment::CorrelationCoefficient => "",
+            Moment::DifferentialPhase => "deg",
+            Moment::SpecificDifferentialPhase => "deg/km",
+        }
+    }
+
+    pub fn threshold_kind(&self) -> ThresholdKind {
+        match self {
+            Moment::Velocity
+            | Moment::DifferentialReflectivity
+            | Moment::SpecificDifferentialPhase => ThresholdKind::Magnitude,
+            _ => ThresholdKind::Minimum,
+        }
+    }
+
+    pub fn default_threshold(&self) -> f32 {
+        match self {
+            Moment::Reflectivity => 0.5,
+            Moment::CorrelationCoefficient => 0.2,
+            _ => 0.0,
+        }
+    }
+
+    /// The span of values, in this moment's units, the transfer function editor and volume
+    /// rendering cover.
+    pub fn value_range(&self) -> (f32, f32) {
+        match self {
+            Moment::Reflectivity => (0.0, 75.0),
+            Moment::Velocity => (-40.0, 40.0),
+            Moment::SpectrumWidth => (0.0, 15.0),
+            Moment::DifferentialReflectivity => (-4.0, 8.0),
+            Moment::CorrelationCoefficient => (0.2, 1.05),
+            Moment::DifferentialPhase => (0.0, 360.0),
+            Moment::SpecificDifferentialPhase => (-2.0, 7.0),
+        }
+    }
+
+    /// The difference in this moment's units weighted like a kilometer of distance when clustering.
+    pub fn cluster_value_scale(&self) -> f32 {
+        match self {
+            Moment::Reflectivity => 5.0,
+            Moment::Velocity => 5.0,
+            Moment::SpectrumWidth => 2.0,
+            Moment::DifferentialReflectivity => 1.0,
+            Moment::CorrelationCoefficient => 0.05,
+            Moment::DifferentialPhase => 20.0,
+            Moment::SpecificDifferentialPhase => 1.0,
+        }
+    }
+
+    /// Whether a gate's value passes the threshold per this moment's threshold semantics.
+    pub fn passes_threshold(&self, value: f32, threshold: f32) -> bool {
+        match self.threshold_kind() {
+            ThresholdKind::Minimum => value > threshold,
+            ThresholdKind::Magnitude => value.abs() > threshold,
+        }
+    }
 }
 
 #[derive(PartialEq, Clone)]
@@ -30,7 +364,27 @@ pub struct DataParams {
     pub site: String,
     pub date: NaiveDate,
     pub time: NaiveTime,
+    pub scan_selection: ScanSelection,
+    /// Maximum minutes between the target time and the selected scan, if limited.
+    pub scan_tolerance: Option<u32>,
+    pub sweep_selection: SweepSelection,
+    pub moment: Moment,
+    pub threshold: f32,
+    /// Effective earth radius multiplier used to model beam refraction, e.g. 4/3.
+    pub refraction_factor: f32,
+    pub sampling_mode: SamplingMode,
+    /// Keep every Nth gate in step sampling.
     pub sampling: u16,
+    /// Maximum number of points kept by budget and weighted sampling.
+    pub point_budget: u32,
+    pub voxel_size_m: f32,
+    /// Gates at or above this value, in the moment's units, are always kept by weighted sampling.
+    pub keep_above: f32,
     pub clustering_mode: ClusteringMode,
     pub clustering_threshold: f32,
+    /// Radius in meters within which neighboring points are counted for the density field.
+    pub density_radius_m: f32,
+    /// Cartesian grid the volume is interpolated onto, if gridding is enabled.
+    pub grid: Option<GridParams>,
+    pub source: DataSource,
 }
@@ -1,171 +1,697 @@
-use crate::data::{get_data, get_points, ColoredPoint, BELOW_THRESHOLD};
-use crate::param::DataParams;
+use crate::cache::Cache;
+use crate::color_table::ColorTable;
+use crate::data::{
+    get_data, get_metadata, get_points, ColoredPoint, RgbColor, VolumeMetadata, BELOW_THRESHOLD,
+};
+use crate::grid::Grid;
+use crate::param::{ClusteringMode, DataParams, DensityColormap, Moment};
 use crate::result::Result;
-use crate::state::{ProcessingStatistics, State};
-use dbscan::Classification;
+use crate::sampling::sample_points;
+use crate::spatial::SpatialIndex;
+use crate::state::{
+    check_cancelled, ProcessingError, ProcessingStage, ProcessingStatistics, State,
+};
+use hsl::HSL;
+use rayon::prelude::*;
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 use std::time::Instant;
+use three_d::InnerSpace;
+use tokio_util::sync::CancellationToken;
 
-pub fn do_fetch_and_process(data_params: DataParams, state: Arc<Mutex<State>>) {
-    tokio::spawn(async move {
-        fetch_and_process(data_params, state)
-            .await
-            .expect("fetch and processes successfully");
-    });
-}
+/// Starts fetching and processing a volume in the background, cancelling any job already in flight
+/// so the latest request always wins.
+pub fn do_fetch_and_process(
+    data_params: DataParams,
+    state: Arc<Mutex<State>>,
+    cache: Arc<Mutex<Cache>>,
+) {
+    let cancel = CancellationToken::new();
 
-pub async fn fetch_and_process(data_params: DataParams, state: Arc<Mutex<State>>) -> Result<()> {
     {
         let mut state = state.lock().unwrap();
 
-        if state.processing {
-            panic!("cannot process concurrently")
+        if let Some(previous_job) = state.job.replace(cancel.clone()) {
+            println!("Cancelling in-flight fetch/processing...");
+            previous_job.cancel();
         }
 
         state.processing = true;
+        state.error = None;
     }
 
+    tokio::spawn(async move {
+        fetch_and_process(data_params, state, cache, cancel).await;
+    });
+}
+
+/// Fetches and processes the requested volume, recording either the resulting points or the error
+/// which stopped processing in the shared state. A cancelled job leaves the state untouched since
+/// it now belongs to the job which superseded it.
+pub async fn fetch_and_process(
+    data_params: DataParams,
+    state: Arc<Mutex<State>>,
+    cache: Arc<Mutex<Cache>>,
+    cancel: CancellationToken,
+) {
+    let mut stage = ProcessingStage::Load;
     let mut stats = ProcessingStatistics::default();
 
-    let decoded = get_data(
-        &data_params.site,
-        &data_params.date,
-        &data_params.time,
-        &mut stats,
-    )
-    .await?;
+    let result = process(&data_params, &cache, &cancel, &mut stage, &mut stats).await;
+
+    // Jobs are only cancelled while the state is locked, so this check can't race a new request
+    let mut state = state.lock().unwrap();
+    if cancel.is_cancelled() {
+        println!("Fetch/processing cancelled during {:?}.", stage);
+        return;
+    }
+
+    state.job = None;
+    state.processing = false;
+
+    match result {
+        Ok((points, metadata, grid)) => {
+            state.points = Some(points);
+            state.metadata = Some(metadata);
+            state.grid = grid;
+            state.statistics = Some(stats);
+
+            println!("Done fetch/processing!");
+        }
+        Err(error) => {
+            println!("Fetch/processing failed during {:?}: {}", stage, error);
+            state.error = Some(ProcessingError { stage, error });
+        }
+    }
+}
+
+async fn process(
+    data_params: &DataParams,
+    cache: &Mutex<Cache>,
+    cancel: &CancellationToken,
+    stage: &mut ProcessingStage,
+    stats: &mut ProcessingStatistics,
+) -> Result<(Vec<ColoredPoint>, VolumeMetadata, Option<Grid>)> {
+    let decoded = get_data(data_params, cache, cancel, stage, stats).await?;
+    let metadata = get_metadata(&decoded);
 
+    *stage = ProcessingStage::Pointing;
     let pointing_start = Instant::now();
-    let points = get_points(&decoded, 0.5);
+    let points = get_points(
+        &decoded,
+        data_params.moment,
+        data_params.threshold,
+        data_params.refraction_factor,
+        data_params.sweep_selection,
+        cancel,
+    )?;
     stats.pointing_ms = pointing_start.elapsed().as_millis();
 
+    let grid = match data_params.grid {
+        Some(ref grid_params) => {
+            check_cancelled(cancel)?;
+            *stage = ProcessingStage::Gridding;
+            let gridding_start = Instant::now();
+            let grid = Grid::from_points(
+                &points,
+                data_params.moment,
+                grid_params,
+                data_params.refraction_factor,
+                cancel,
+            )?;
+            stats.gridding_ms = gridding_start.elapsed().as_millis();
+
+            println!(
+                "Gridded volume to {:?} cells, {} with data.",
+                grid.dimensions,
+                grid.filled_count()
+            );
+            Some(grid)
+        }
+        None => None,
+    };
+
     // Sample dataset to speed processing
-    let mut sampled_points = points
-        .into_iter()
-        .step_by(data_params.sampling as usize)
-        .collect::<Vec<_>>();
-    println!("Scan contains {} points.", sampled_points.len());
-
-    // todo: we need to weight and rescale geometrically before clustering
-    // todo: in addition to result/density, weight should consider gate distance
-
-    // println!("Clustering points...");
-    // let (clusters, unclustered) = do_dbscan_clustering(sampled_points.clone());
-    // println!(
-    //     "Found {} clusters with {} remaining unclustered points.",
-    //     clusters.len(),
-    //     unclustered.len()
-    // );
-
-    // todo: coloring clusters to debug
-
-    // let mut index = 0.0;
-    // let mut get_color = || {
-    //     let phi = (1.0 + 5.0_f64.sqrt()) / 2.0;
-    //     let n = index * phi - (index * phi).floor();
-    //     let color = HSL {
-    //         h: n * 256.0,
-    //         s: 1.0,
-    //         l: 0.5,
-    //     };
-    //     index += 1.0;
-    //     return color.to_rgb();
-    // };
-    //
-    // println!("Recoloring clusters for debugging.");
-    // let mut recolored_points = Vec::new();
-    // let unclustered_color = (255, 255, 255);
-    // for mut point in unclustered {
-    //     point.1 = unclustered_color;
-    //     recolored_points.push(point);
-    // }
-    // for cluster in clusters {
-    //     let cluster_color = get_color();
-    //     for mut point in cluster {
-    //         point.1 = cluster_color;
-    //         recolored_points.push(point);
-    //     }
-    // }
-
-    color_points(&mut sampled_points);
+    check_cancelled(cancel)?;
+    *stage = ProcessingStage::Sampling;
+    let sampling_start = Instant::now();
+    let mut sampled_points = sample_points(points, data_params);
+    stats.sampling_ms = sampling_start.elapsed().as_millis();
+    let folded_count = sampled_points.iter().filter(|p| p.folded).count();
+    println!(
+        "Scan contains {} points and {} range-folded gates.",
+        sampled_points.len() - folded_count,
+        folded_count
+    );
 
-    let mut state = state.lock().unwrap();
-    state.points = Some(sampled_points);
-    state.processing = false;
-    state.statistics = Some(stats);
+    // Every neighborhood query below shares one index over the sampled points
+    let index = SpatialIndex::new(&sampled_points, data_params.density_radius_m);
 
-    println!("Done fetch/processing!");
+    check_cancelled(cancel)?;
+    *stage = ProcessingStage::Clustering;
+    let clustering_start = Instant::now();
+    let (clusters, unclustered) = match data_params.clustering_mode {
+        ClusteringMode::DBSCAN => do_dbscan_clustering(
+            &sampled_points,
+            &index,
+            data_params.moment,
+            data_params.clustering_threshold,
+            cancel,
+        )?,
+        ClusteringMode::KNN => do_knn_clustering(
+            &sampled_points,
+            &index,
+            data_params.moment,
+            data_params.clustering_threshold,
+            cancel,
+        )?,
+        ClusteringMode::None => (Vec::new(), (0..sampled_points.len()).collect()),
+    };
+    println!(
+        "Found {} clusters with {} remaining unclustered points.",
+        clusters.len(),
+        unclustered.len()
+    );
+    color_clusters(&mut sampled_points, &clusters, &unclustered);
+    stats.clustering_ms = clustering_start.elapsed().as_millis();
 
-    Ok(())
+    check_cancelled(cancel)?;
+    *stage = ProcessingStage::Coloring;
+    let coloring_start = Instant::now();
+    color_density(
+        &mut sampled_points,
+        &index,
+        data_params.density_radius_m,
+        cancel,
+    )?;
+    stats.coloring_ms = coloring_start.elapsed().as_millis();
+
+    Ok((sampled_points, metadata, grid))
 }
 
-// Returns: (clustered points, unclustered points)
-fn do_dbscan_clustering(points: Vec<ColoredPoint>) -> (Vec<Vec<ColoredPoint>>, Vec<ColoredPoint>) {
-    let mut clusters = HashMap::new();
-    let mut unclustered_points = Vec::new();
+/// Minimum number of points, including itself, within a point's neighborhood for it to be a
+/// DBSCAN core point.
+const DBSCAN_MIN_POINTS: usize = 5;
+
+/// Range over which a point's clustering neighborhood doubles in size, so the sparser gates far
+/// from the radar, where the beam has spread, still cluster.
+const CLUSTER_RANGE_SCALE_M: f32 = 50000.0;
+
+const UNCLUSTERED_COLOR: RgbColor = (80, 80, 80);
+
+/// Number of points or neighborhood queries between checks for cancellation in long stages.
+const CANCEL_CHECK_INTERVAL: usize = 1024;
+
+/// Clusters points with DBSCAN where the neighborhood radius is the clustering threshold in
+/// kilometers, grown with each point's range and shrunk by value differences.
+/// Returns: (clustered point indices, unclustered point indices)
+fn do_dbscan_clustering(
+    points: &[ColoredPoint],
+    index: &SpatialIndex,
+    moment: Moment,
+    threshold_km: f32,
+    cancel: &CancellationToken,
+) -> Result<(Vec<Vec<usize>>, Vec<usize>)> {
+    let mut query_count = 0;
+    let mut neighborhood = |point_index: usize| -> Result<Vec<usize>> {
+        query_count += 1;
+        if query_count % CANCEL_CHECK_INTERVAL == 0 {
+            check_cancelled(cancel)?;
+        }
+
+        Ok(
+            cluster_neighbors(points, index, point_index, moment, threshold_km)
+                .map(|(neighbor, _)| neighbor)
+                .collect::<Vec<_>>(),
+        )
+    };
+
+    let mut clusters: Vec<Vec<usize>> = Vec::new();
+    let mut labels: Vec<Option<usize>> = vec![None; points.len()];
+    let mut visited = vec![false; points.len()];
+
+    // Range-folded gates have no value to cluster on so they're always left unclustered
+    for point_index in 0..points.len() {
+        if visited[point_index] || points[point_index].folded {
+            continue;
+        }
+
+        visited[point_index] = true;
+        let neighbors = neighborhood(point_index)?;
+        if neighbors.len() < DBSCAN_MIN_POINTS {
+            continue;
+        }
 
-    let vectorized_points: Vec<Vec<f32>> = points
-        .iter()
-        .map(|p| vec![p.pos.x, p.pos.y, p.pos.z])
-        .collect::<Vec<_>>();
+        let cluster = clusters.len();
+        clusters.push(Vec::new());
 
-    let classifications = dbscan::cluster(0.05, 5, &vectorized_points);
+        // Points are labeled as they're queued so each joins the queue at most once, and only
+        // core points' neighborhoods are expanded; border points join but don't expand
+        let mut queue = Vec::new();
+        let mut enqueue = |neighbors: Vec<usize>, queue: &mut Vec<usize>| {
+            for neighbor in neighbors {
+                if labels[neighbor].is_none() {
+                    labels[neighbor] = Some(cluster);
+                    clusters[cluster].push(neighbor);
+                    queue.push(neighbor);
+                }
+            }
+        };
+        enqueue(neighbors, &mut queue);
 
-    for (colored_point, classification) in points.into_iter().zip(classifications) {
-        match classification {
-            Classification::Core(cluster) => {
-                if !clusters.contains_key(&cluster) {
-                    clusters.insert(cluster, Vec::new());
+        while let Some(neighbor) = queue.pop() {
+            if !visited[neighbor] {
+                visited[neighbor] = true;
+                let neighbors = neighborhood(neighbor)?;
+                if neighbors.len() >= DBSCAN_MIN_POINTS {
+                    enqueue(neighbors, &mut queue);
                 }
+            }
+        }
+    }
+
+    let unclustered = (0..points.len())
+        .filter(|&point_index| labels[point_index].is_none())
+        .collect();
+
+    Ok((clusters, unclustered))
+}
+
+/// Number of nearest neighbors considered for each point when building the KNN graph.
+const KNN_NEIGHBORS: usize = 8;
+
+/// Clusters points as the connected components of their mutual k-nearest-neighbor graph, only
+/// linking neighbors within the clustering threshold in kilometers (measured as for DBSCAN).
+/// Components smaller than a DBSCAN core neighborhood are left unclustered.
+/// Returns: (clustered point indices, unclustered point indices)
+fn do_knn_clustering(
+    points: &[ColoredPoint],
+    index: &SpatialIndex,
+    moment: Moment,
+    threshold_km: f32,
+    cancel: &CancellationToken,
+) -> Result<(Vec<Vec<usize>>, Vec<usize>)> {
+    let neighbors = (0..points.len())
+        .into_par_iter()
+        .map(|point_index| {
+            if point_index % CANCEL_CHECK_INTERVAL == 0 {
+                check_cancelled(cancel)?;
+            }
+            if points[point_index].folded {
+                return Ok(Vec::new());
+            }
+
+            let max_distance_m = threshold_km * 1000.0 * range_weight(&points[point_index]);
+            Ok(index
+                .nearest(
+                    index.position(point_index),
+                    KNN_NEIGHBORS + 1,
+                    Some(max_distance_m),
+                )
+                .into_iter()
+                .map(|(neighbor, _)| neighbor)
+                .filter(|&neighbor| {
+                    neighbor != point_index
+                        && !points[neighbor].folded
+                        && cluster_distance_km(points, index, point_index, neighbor, moment)
+                            <= threshold_km
+                })
+                .collect::<Vec<_>>())
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    // Union-find over mutual neighbor links
+    let mut parents = (0..points.len()).collect::<Vec<_>>();
+    for (point_index, point_neighbors) in neighbors.iter().enumerate() {
+        if point_index % CANCEL_CHECK_INTERVAL == 0 {
+            check_cancelled(cancel)?;
+        }
 
-                clusters.get_mut(&cluster).unwrap().push(colored_point);
+        for &neighbor in point_neighbors {
+            if neighbors[neighbor].contains(&point_index) {
+                let root = find_root(&mut parents, point_index);
+                let neighbor_root = find_root(&mut parents, neighbor);
+                parents[root] = neighbor_root;
             }
-            _ => unclustered_points.push(colored_point),
         }
     }
 
-    (
-        clusters
-            .into_iter()
-            .map(|(_, points)| points)
-            .collect::<Vec<_>>(),
-        unclustered_points,
-    )
+    let mut components = HashMap::new();
+    for point_index in 0..points.len() {
+        components
+            .entry(find_root(&mut parents, point_index))
+            .or_insert_with(Vec::new)
+            .push(point_index);
+    }
+
+    // Components are filled in point order, so ordering them by their first point keeps cluster
+    // indices, and so their colors, the same from run to run
+    let mut components = components.into_values().collect::<Vec<_>>();
+    components.sort_unstable_by_key(|component| component[0]);
+
+    let mut clusters = Vec::new();
+    let mut unclustered = Vec::new();
+    for component in components {
+        if component.len() >= DBSCAN_MIN_POINTS && !points[component[0]].folded {
+            clusters.push(component);
+        } else {
+            unclustered.extend(component);
+        }
+    }
+
+    Ok((clusters, unclustered))
+}
+
+fn find_root(parents: &mut [usize], mut index: usize) -> usize {
+    while parents[index] != index {
+        parents[index] = parents[parents[index]];
+        index = parents[index];
+    }
+
+    index
+}
+
+/// Factor a point's clustering neighborhood is grown by with range.
+fn range_weight(point: &ColoredPoint) -> f32 {
+    1.0 + point.gate.range_m / CLUSTER_RANGE_SCALE_M
+}
+
+/// Clustering distance from a point to another: their separation in kilometers shrunk by the
+/// first point's range weight, combined with their value difference in kilometer-equivalent units
+/// so gates with very different values don't cluster together.
+fn cluster_distance_km(
+    points: &[ColoredPoint],
+    index: &SpatialIndex,
+    point_index: usize,
+    other_index: usize,
+    moment: Moment,
+) -> f32 {
+    let separation_km = (index.position(other_index) - index.position(point_index)).magnitude()
+        / 1000.0
+        / range_weight(&points[point_index]);
+    let value_difference =
+        (points[other_index].value - points[point_index].value) / moment.cluster_value_scale();
+
+    (separation_km * separation_km + value_difference * value_difference).sqrt()
+}
+
+/// A point's unfolded neighbors, including itself, within the clustering threshold with their
+/// clustering distances.
+fn cluster_neighbors<'a>(
+    points: &'a [ColoredPoint],
+    index: &'a SpatialIndex,
+    point_index: usize,
+    moment: Moment,
+    threshold_km: f32,
+) -> impl Iterator<Item = (usize, f32)> + 'a {
+    let max_distance_m = threshold_km * 1000.0 * range_weight(&points[point_index]);
+
+    index
+        .within_radius(index.position(point_index), max_distance_m)
+        .into_iter()
+        .filter(|&neighbor| !points[neighbor].folded)
+        .map(move |neighbor| {
+            (
+                neighbor,
+                cluster_distance_km(points, index, point_index, neighbor, moment),
+            )
+        })
+        .filter(move |(_, distance)| *distance <= threshold_km)
+}
+
+/// Assigns each cluster a distinct color, spreading hues by the golden ratio, and greys out the
+/// unclustered points.
+fn color_clusters(points: &mut [ColoredPoint], clusters: &[Vec<usize>], unclustered: &[usize]) {
+    let golden_ratio = (1.0 + 5.0_f64.sqrt()) / 2.0;
+
+    for &point_index in unclustered {
+        points[point_index].cluster = UNCLUSTERED_COLOR;
+    }
+
+    for (cluster_index, cluster) in clusters.iter().enumerate() {
+        let hue = (cluster_index as f64 * golden_ratio).fract();
+        let cluster_color = HSL {
+            h: hue * 360.0,
+            s: 1.0,
+            l: 0.5,
+        }
+        .to_rgb();
+
+        for &point_index in cluster {
+            points[point_index].cluster = cluster_color;
+        }
+    }
 }
 
-fn color_points(points: &mut Vec<ColoredPoint>) {
+/// Colors points' values per the color table, their densities per the colormap, and their hybrid
+/// colors by shading their value colors by their density, so it can run again whenever the table or
+/// colormap changes. Must run after `color_density`. Range-folded points take the table's RF color
+/// in every mode but clustering.
+pub fn color_points(points: &mut [ColoredPoint], table: &ColorTable, colormap: DensityColormap) {
+    let range_folded = table.range_folded_color();
+    let density_colors = density_colors(colormap);
+
     for point in points {
-        point.raw = if point.strength < 5.0 || point.strength == BELOW_THRESHOLD {
+        if point.folded {
+            point.raw = range_folded;
+            point.density = range_folded;
+            point.hybrid = range_folded;
+            continue;
+        }
+
+        point.raw = if point.value == BELOW_THRESHOLD {
             (0, 0, 0)
-        } else if point.strength >= 5.0 && point.strength < 10.0 {
-            (0x40, 0xe8, 0xe3)
-        } else if point.strength >= 10.0 && point.strength < 15.0 {
-            (0x26, 0xa4, 0xfa)
-        } else if point.strength >= 15.0 && point.strength < 20.0 {
-            (0x00, 0x30, 0xed)
-        } else if point.strength >= 20.0 && point.strength < 25.0 {
-            (0x49, 0xfb, 0x3e)
-        } else if point.strength >= 25.0 && point.strength < 30.0 {
-            (0x36, 0xc2, 0x2e)
-        } else if point.strength >= 30.0 && point.strength < 35.0 {
-            (0x27, 0x8c, 0x1e)
-        } else if point.strength >= 35.0 && point.strength < 40.0 {
-            (0xfe, 0xf5, 0x43)
-        } else if point.strength >= 40.0 && point.strength < 45.0 {
-            (0xeb, 0xb4, 0x33)
-        } else if point.strength >= 45.0 && point.strength < 50.0 {
-            (0xf6, 0x95, 0x2e)
-        } else if point.strength >= 50.0 && point.strength < 55.0 {
-            (0xf8, 0x0a, 0x26)
-        } else if point.strength >= 55.0 && point.strength < 60.0 {
-            (0xcb, 0x05, 0x16)
-        } else if point.strength >= 60.0 && point.strength < 65.0 {
-            (0xa9, 0x08, 0x13)
-        } else if point.strength >= 65.0 && point.strength < 70.0 {
-            (0xee, 0x34, 0xfa)
         } else {
-            (0xff, 0xff, 0xFF)
+            table.rgb(point.value)
+        };
+        point.density = gradient_color(density_colors, point.density_level);
+
+        let brightness =
+            HYBRID_MIN_BRIGHTNESS + (1.0 - HYBRID_MIN_BRIGHTNESS) * point.density_level;
+        let shade = |channel: u8| (channel as f32 * brightness).round() as u8;
+        point.hybrid = (shade(point.raw.0), shade(point.raw.1), shade(point.raw.2));
+    }
+}
+
+/// Colormaps as evenly spaced stops from lowest to highest density.
+const VIRIDIS_COLORS: &[RgbColor] = &[
+    (0x44, 0x01, 0x54),
+    (0x3b, 0x52, 0x8b),
+    (0x21, 0x90, 0x8c),
+    (0x5d, 0xc8, 0x63),
+    (0xfd, 0xe7, 0x25),
+];
+
+const INFERNO_COLORS: &[RgbColor] = &[
+    (0x00, 0x00, 0x04),
+    (0x56, 0x10, 0x6e),
+    (0xbb, 0x37, 0x54),
+    (0xf9, 0x8e, 0x09),
+    (0xfc, 0xff, 0xa4),
+];
+
+const GRAYSCALE_COLORS: &[RgbColor] = &[(0x20, 0x20, 0x20), (0xff, 0xff, 0xff)];
+
+/// Fraction of a hybrid color's brightness kept for the sparsest points.
+const HYBRID_MIN_BRIGHTNESS: f32 = 0.2;
+
+fn density_colors(colormap: DensityColormap) -> &'static [RgbColor] {
+    match colormap {
+        DensityColormap::Viridis => VIRIDIS_COLORS,
+        DensityColormap::Inferno => INFERNO_COLORS,
+        DensityColormap::Grayscale => GRAYSCALE_COLORS,
+    }
+}
+
+/// Sets points' density levels from the number of neighbors within the radius, log-normalized
+/// against the densest point, for `color_points` to color by.
+fn color_density(
+    points: &mut [ColoredPoint],
+    index: &SpatialIndex,
+    radius_m: f32,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    let neighbor_counts = (0..points.len())
+        .into_par_iter()
+        .map(|point_index| {
+            if point_index % CANCEL_CHECK_INTERVAL == 0 {
+                check_cancelled(cancel)?;
+            }
+            if points[point_index].folded {
+                return Ok(0);
+            }
+
+            // Excluding the point itself
+            let mut count = 0;
+            index.for_each_within_radius(index.position(point_index), radius_m, |neighbor| {
+                if neighbor != point_index && !points[neighbor].folded {
+                    count += 1;
+                }
+            });
+            Ok(count)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let max_count = neighbor_counts.iter().copied().max().unwrap_or(0);
+
+    for (point, count) in points.iter_mut().zip(neighbor_counts) {
+        if point.folded {
+            continue;
+        }
+
+        point.density_level = if max_count == 0 {
+            0.0
+        } else {
+            (count as f32).ln_1p() / (max_count as f32).ln_1p()
+        };
+    }
+
+    Ok(())
+}
+
+/// Linearly interpolates evenly spaced color stops at a position from 0 to 1.
+fn gradient_color(stops: &[RgbColor], position: f32) -> RgbColor {
+    let scaled = position.clamp(0.0, 1.0) * (stops.len() - 1) as f32;
+    let index = (scaled.floor() as usize).min(stops.len() - 2);
+    let fraction = scaled - index as f32;
+
+    let (from, to) = (stops[index], stops[index + 1]);
+    let mix = |from: u8, to: u8| (from as f32 + (to as f32 - from as f32) * fraction).round() as u8;
+
+    (mix(from.0, to.0), mix(from.1, to.1), mix(from.2, to.2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::GateGeometry;
+    use crate::result::Error;
+    use crate::RENDER_RATIO_TO_M;
+    use three_d::vec3;
+
+    /// A point at the position in meters, with its gate at the radar so clustering neighborhoods
+    /// aren't grown by range.
+    fn point(x_m: f32, z_m: f32, value: f32) -> ColoredPoint {
+        let gate = GateGeometry {
+            azimuth_rad: 0.0,
+            elevation_rad: 0.0,
+            range_m: 0.0,
+            azimuth_width_rad: 0.0,
+            beam_width_rad: 0.0,
+            gate_length_m: 0.0,
         };
+
+        ColoredPoint::new(vec3(x_m, 0.0, z_m) * RENDER_RATIO_TO_M, gate, value)
+    }
+
+    /// A 3 by 3 blob of points 1 km apart centered on the position in meters.
+    fn blob(x_m: f32, z_m: f32) -> Vec<ColoredPoint> {
+        (-1..=1)
+            .flat_map(|x| (-1..=1).map(move |z| (x as f32, z as f32)))
+            .map(|(x, z)| point(x_m + x * 1000.0, z_m + z * 1000.0, 30.0))
+            .collect()
+    }
+
+    fn dbscan(points: &[ColoredPoint]) -> (Vec<Vec<usize>>, Vec<usize>) {
+        let index = SpatialIndex::new(points, 5000.0);
+        let (mut clusters, mut unclustered) = do_dbscan_clustering(
+            points,
+            &index,
+            Moment::Reflectivity,
+            5.0,
+            &CancellationToken::new(),
+        )
+        .unwrap();
+
+        for cluster in &mut clusters {
+            cluster.sort_unstable();
+        }
+        unclustered.sort_unstable();
+        (clusters, unclustered)
+    }
+
+    #[test]
+    fn dbscan_finds_separated_blobs_and_noise() {
+        let mut points = blob(0.0, 0.0);
+        points.push(point(50000.0, 0.0, 30.0));
+        points.extend(blob(100000.0, 0.0));
+        points.push(point(0.0, 50000.0, 30.0));
+
+        let (clusters, unclustered) = dbscan(&points);
+
+        assert_eq!(
+            clusters,
+            vec![(0..9).collect::<Vec<_>>(), (10..19).collect::<Vec<_>>()]
+        );
+        assert_eq!(unclustered, vec![9, 19]);
+    }
+
+    #[test]
+    fn dbscan_leaves_folded_and_dissimilar_points_unclustered() {
+        let mut points = blob(0.0, 0.0);
+        let gate = points[0].gate;
+        points.push(ColoredPoint::new_folded(vec3(0.0, 0.0, 0.0), gate));
+        // In the blob but far from its values
+        points.push(point(500.0, 500.0, 70.0));
+
+        let (clusters, unclustered) = dbscan(&points);
+
+        assert_eq!(clusters, vec![(0..9).collect::<Vec<_>>()]);
+        assert_eq!(unclustered, vec![9, 10]);
+    }
+
+    #[test]
+    fn knn_clusters_are_ordered_by_first_point() {
+        let mut points = blob(0.0, 0.0);
+        points.push(point(50000.0, 0.0, 30.0));
+        points.extend(blob(100000.0, 0.0));
+
+        let index = SpatialIndex::new(&points, 5000.0);
+        let (clusters, unclustered) = do_knn_clustering(
+            &points,
+            &index,
+            Moment::Reflectivity,
+            5.0,
+            &CancellationToken::new(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            clusters,
+            vec![(0..9).collect::<Vec<_>>(), (10..19).collect::<Vec<_>>()]
+        );
+        assert_eq!(unclustered, vec![9]);
+    }
+
+    #[test]
+    fn clustering_honors_cancellation() {
+        let points = (0..CANCEL_CHECK_INTERVAL * 2)
+            .map(|x| point(x as f32 * 1000.0, 0.0, 30.0))
+            .collect::<Vec<_>>();
+        let index = SpatialIndex::new(&points, 5000.0);
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+
+        assert!(matches!(
+            do_dbscan_clustering(&points, &index, Moment::Reflectivity, 5.0, &cancel),
+            Err(Error::CancelledError)
+        ));
+        assert!(matches!(
+            do_knn_clustering(&points, &index, Moment::Reflectivity, 5.0, &cancel),
+            Err(Error::CancelledError)
+        ));
+    }
+
+    #[test]
+    fn dbscan_needs_enough_neighbors() {
+        // Four points, each with only four neighbors including itself
+        let points = (0..4)
+            .map(|x| point(x as f32 * 1000.0, 0.0, 30.0))
+            .collect::<Vec<_>>();
+
+        let (clusters, unclustered) = dbscan(&points);
+
+        assert!(clusters.is_empty());
+        assert_eq!(unclustered, vec![0, 1, 2, 3]);
     }
 }
@@ -1,15 +1,44 @@
+use std::fmt::{Display, Formatter};
+
 pub type Result<T> = std::result::Result<T, Error>;
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug)]
 pub enum Error {
-    NEXRADError(nexrad::result::Error),
+    NEXRADError(Box<nexrad::result::Error>),
     IOError(std::io::Error),
     WindowError(three_d::WindowError),
+    ScanNotFoundError(String),
+    DecodeError(String),
+    TransferFunctionError(String),
+    ColorTableError(String),
+    BenchmarkMismatchError(String),
+    CancelledError,
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::NEXRADError(err) => write!(f, "NEXRAD error: {:?}", err),
+            Error::IOError(err) => write!(f, "I/O error: {}", err),
+            Error::WindowError(err) => write!(f, "window error: {}", err),
+            Error::ScanNotFoundError(message) => write!(f, "no scan found {}", message),
+            Error::DecodeError(message) => write!(f, "failed to decode file: {}", message),
+            Error::TransferFunctionError(message) => {
+                write!(f, "invalid transfer function: {}", message)
+            }
+            Error::ColorTableError(message) => write!(f, "invalid color table: {}", message),
+            Error::BenchmarkMismatchError(message) => {
+                write!(f, "benchmark results differ: {}", message)
+            }
+            Error::CancelledError => write!(f, "cancelled by a newer request"),
+        }
+    }
 }
 
 impl From<nexrad::result::Error> for Error {
     fn from(err: nexrad::result::Error) -> Error {
-        Error::NEXRADError(err)
+        Error::NEXRADError(Box::new(err))
     }
 }
 
@@ -0,0 +1,308 @@
+use crate::data::ColoredPoint;
+use crate::param::{DataParams, Moment, SamplingMode, ThresholdKind};
+use crate::RENDER_RATIO_TO_M;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+/// Reduces the points per the sampling mode, keeping the surviving points in their original order.
+/// Step sampling keeps every Nth point in gate order; the other modes are independent of it.
+pub fn sample_points(points: Vec<ColoredPoint>, data_params: &DataParams) -> Vec<ColoredPoint> {
+    match data_params.sampling_mode {
+        SamplingMode::Step => points
+            .into_iter()
+            .step_by(data_params.sampling as usize)
+            .collect(),
+        SamplingMode::Budget => {
+            let keys = points.iter().map(gate_random).collect::<Vec<_>>();
+            keep_top(points, &keys, data_params.point_budget as usize)
+        }
+        SamplingMode::VoxelGrid => voxel_grid(points, data_params.voxel_size_m, data_params.moment),
+        SamplingMode::Weighted => weighted(
+            points,
+            data_params.moment,
+            data_params.keep_above,
+            data_params.point_budget as usize,
+        ),
+    }
+}
+
+/// Keeps the single most important point in each cubic voxel of the specified size in meters.
+fn voxel_grid(points: Vec<ColoredPoint>, voxel_size_m: f32, moment: Moment) -> Vec<ColoredPoint> {
+    let voxel_size = voxel_size_m * RENDER_RATIO_TO_M;
+
+    let mut voxels: HashMap<[i32; 3], usize> = HashMap::new();
+    for (index, point) in points.iter().enumerate() {
+        let voxel =
+            [point.pos.x, point.pos.y, point.pos.z].map(|axis| (axis / voxel_size).floor() as i32);
+
+        voxels
+            .entry(voxel)
+            .and_modify(|kept| {
+                if compare_importance(point, &points[*kept], moment) == Ordering::Greater {
+                    *kept = index;
+                }
+            })
+            .or_insert(index);
+    }
+
+    let mut kept = vec![false; points.len()];
+    for index in voxels.into_values() {
+        kept[index] = true;
+    }
+
+    points
+        .into_iter()
+        .zip(kept)
+        .filter_map(|(point, kept)| kept.then_some(point))
+        .collect()
+}
+
+/// Keeps every point at or above the value, filling the rest of the budget with a random sample of
+/// the weaker points weighted toward stronger values.
+fn weighted(
+    points: Vec<ColoredPoint>,
+    moment: Moment,
+    keep_above: f32,
+    budget: usize,
+) -> Vec<ColoredPoint> {
+    let importances = points
+        .iter()
+        .map(|point| importance(point, moment))
+        .collect::<Vec<_>>();
+
+    let (min, max) = importances
+        .iter()
+        .filter(|importance| importance.is_finite())
+        .fold(
+            (f32::INFINITY, f32::NEG_INFINITY),
+            |(min, max), importance| (min.min(*importance), max.max(*importance)),
+        );
+    let spread = (max - min).max(f32::EPSILON);
+
+    // Weighted sampling without replacement (Efraimidis-Spirakis): keep the largest u^(1/w), and
+    // rank points above the keep value ahead of every sampled point
+    let keys = points
+        .iter()
+        .zip(&importances)
+        .map(|(point, importance)| {
+            if *importance >= keep_above {
+                return f32::INFINITY;
+            }
+
+            let weight = if importance.is_finite() {
+                WEIGHT_FLOOR + (importance - min) / spread
+            } else {
+                WEIGHT_FLOOR
+            };
+
+            gate_random(point).powf(1.0 / weight)
+        })
+        .collect::<Vec<_>>();
+
+    let always_kept = keys.iter().filter(|key| key.is_infinite()).count();
+    keep_top(points, &keys, budget.max(always_kept))
+}
+
+/// Weight of the weakest points relative to the strongest in weighted sampling.
+const WEIGHT_FLOOR: f32 = 0.1;
+
+/// Keeps the points with the largest keys up to the budget.
+fn keep_top(points: Vec<ColoredPoint>, keys: &[f32], budget: usize) -> Vec<ColoredPoint> {
+    if points.len() <= budget {
+        return points;
+    }
+
+    let mut order = (0..points.len()).collect::<Vec<_>>();
+    order.select_nth_unstable_by(budget, |a, b| keys[*b].total_cmp(&keys[*a]));
+
+    let mut kept = vec![false; points.len()];
+    for &index in &order[..budget] {
+        kept[index] = true;
+    }
+
+    points
+        .into_iter()
+        .zip(kept)
+        .filter_map(|(point, kept)| kept.then_some(point))
+        .collect()
+}
+
+/// How strongly a point should be preserved: its value, or magnitude for signed moments. Range
+/// folded points have no value and are least important.
+fn importance(point: &ColoredPoint, moment: Moment) -> f32 {
+    if point.folded {
+        return f32::NEG_INFINITY;
+    }
+
+    match moment.threshold_kind() {
+        ThresholdKind::Minimum => point.value,
+        ThresholdKind::Magnitude => point.value.abs(),
+    }
+}
+
+fn compare_importance(point: &ColoredPoint, other: &ColoredPoint, moment: Moment) -> Ordering {
+    importance(point, moment).total_cmp(&importance(other, moment))
+}
+
+/// A uniform pseudo-random number in (0, 1] derived from the gate's geometry, so sampling is
+/// repeatable and doesn't depend on the order gates were generated in.
+fn gate_random(point: &ColoredPoint) -> f32 {
+    let gate = &point.gate;
+    let mut hash = (gate.azimuth_rad.to_bits() as u64) << 32 | gate.range_m.to_bits() as u64;
+    hash ^= (gate.elevation_rad.to_bits() as u64).rotate_left(17);
+
+    // SplitMix64 finalizer
+    hash = hash.wrapping_add(0x9e3779b97f4a7c15);
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d049bb133111eb);
+    hash ^= hash >> 31;
+
+    ((hash >> 40) as f32 + 1.0) / (1u64 << 24) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::Args;
+    use crate::data::GateGeometry;
+    use clap::Parser;
+    use three_d::vec3;
+
+    /// 10 radials of 20 gates, with values rising 3 dBZ per gate.
+    fn points() -> Vec<ColoredPoint> {
+        (0..10)
+            .flat_map(|radial| (0..20).map(move |gate| (radial, gate)))
+            .map(|(radial, gate)| {
+                let azimuth_rad = radial as f32 * 0.1;
+                let range_m = 2000.0 + gate as f32 * 250.0;
+                let gate_geometry = GateGeometry {
+                    azimuth_rad,
+                    elevation_rad: 0.01,
+                    range_m,
+                    azimuth_width_rad: 0.1,
+                    beam_width_rad: 0.0166,
+                    gate_length_m: 250.0,
+                };
+
+                ColoredPoint::new(
+                    vec3(azimuth_rad.cos(), 0.0, azimuth_rad.sin()) * range_m * RENDER_RATIO_TO_M,
+                    gate_geometry,
+                    gate as f32 * 3.0,
+                )
+            })
+            .collect()
+    }
+
+    fn data_params(sampling_mode: SamplingMode) -> DataParams {
+        DataParams {
+            sampling_mode,
+            ..Args::parse_from(["test"]).data_params()
+        }
+    }
+
+    /// The kept gates' positions along their radials, comparable regardless of order.
+    fn gate_keys(points: &[ColoredPoint]) -> Vec<(u32, u32)> {
+        let mut keys = points
+            .iter()
+            .map(|point| {
+                (
+                    point.gate.azimuth_rad.to_bits(),
+                    point.gate.range_m.to_bits(),
+                )
+            })
+            .collect::<Vec<_>>();
+        keys.sort_unstable();
+        keys
+    }
+
+    #[test]
+    fn step_keeps_every_nth_point() {
+        let points = points();
+        let data_params = DataParams {
+            sampling: 7,
+            ..data_params(SamplingMode::Step)
+        };
+
+        let sampled = sample_points(points.clone(), &data_params);
+
+        assert_eq!(sampled.len(), 29);
+        for (sampled, original) in sampled.iter().zip(points.iter().step_by(7)) {
+            assert_eq!(sampled.gate.range_m, original.gate.range_m);
+            assert_eq!(sampled.gate.azimuth_rad, original.gate.azimuth_rad);
+        }
+    }
+
+    #[test]
+    fn budget_is_respected_and_repeatable() {
+        let data_params = DataParams {
+            point_budget: 50,
+            ..data_params(SamplingMode::Budget)
+        };
+
+        let sampled = sample_points(points(), &data_params);
+        assert_eq!(sampled.len(), 50);
+        assert_eq!(
+            gate_keys(&sampled),
+            gate_keys(&sample_points(points(), &data_params))
+        );
+
+        // The same gates are kept whatever order they arrive in
+        let mut reversed = points();
+        reversed.reverse();
+        assert_eq!(
+            gate_keys(&sampled),
+            gate_keys(&sample_points(reversed, &data_params))
+        );
+
+        // Budgets beyond the point count keep everything
+        let data_params = DataParams {
+            point_budget: 1000,
+            ..data_params
+        };
+        assert_eq!(sample_points(points(), &data_params).len(), 200);
+    }
+
+    #[test]
+    fn voxel_grid_keeps_the_strongest_point_per_voxel() {
+        let data_params = DataParams {
+            voxel_size_m: 1.0e7,
+            ..data_params(SamplingMode::VoxelGrid)
+        };
+        let sampled = sample_points(points(), &data_params);
+        assert_eq!(sampled.len(), 1);
+        assert_eq!(sampled[0].value, 57.0);
+
+        // Voxels smaller than the gate spacing keep every point
+        let data_params = DataParams {
+            voxel_size_m: 10.0,
+            ..data_params
+        };
+        assert_eq!(sample_points(points(), &data_params).len(), 200);
+    }
+
+    #[test]
+    fn weighted_keeps_strong_points_within_budget() {
+        let data_params = DataParams {
+            point_budget: 80,
+            keep_above: 40.0,
+            ..data_params(SamplingMode::Weighted)
+        };
+
+        // Gates 14 through 19 of every radial are at or above 42 dBZ
+        let sampled = sample_points(points(), &data_params);
+        assert_eq!(sampled.len(), 80);
+        assert_eq!(
+            sampled.iter().filter(|point| point.value >= 40.0).count(),
+            60
+        );
+
+        // Points above the keep value are kept even past the budget
+        let data_params = DataParams {
+            point_budget: 10,
+            ..data_params
+        };
+        let sampled = sample_points(points(), &data_params);
+        assert_eq!(sampled.len(), 60);
+        assert!(sampled.iter().all(|point| point.value >= 40.0));
+    }
+}
@@ -0,0 +1,99 @@
+uniform vec3 cameraPosition;
+uniform float renderRatio;
+uniform float earthRadius;
+uniform vec3 boxMin;
+uniform vec3 boxMax;
+uniform vec3 gridMin;
+uniform vec3 gridSize;
+uniform float stepSize;
+uniform float referenceStep;
+uniform float opacityScale;
+uniform sampler3D values;
+uniform sampler2D transferFunction;
+
+in vec3 pos;
+
+layout (location = 0) out vec4 outColor;
+
+// Upper bound on samples per ray so a tiny step can't stall the GPU
+const int MAX_STEPS = 4096;
+
+// Distances along the ray, in meters, where it enters and leaves the bounding box.
+vec2 box_intersection(vec3 origin, vec3 direction) {
+    vec3 inverse = 1.0 / direction;
+    vec3 t0 = (boxMin / renderRatio - origin) * inverse;
+    vec3 t1 = (boxMax / renderRatio - origin) * inverse;
+    vec3 near = min(t0, t1);
+    vec3 far = max(t0, t1);
+    return vec2(max(max(near.x, near.y), near.z), min(min(far.x, far.y), far.z));
+}
+
+// Distance along the ray, in meters, to where it hits the earth, or a negative value if it misses.
+float earth_intersection(vec3 origin, vec3 direction) {
+    vec3 offset = origin - vec3(0.0, -earthRadius, 0.0);
+    float b = dot(offset, direction);
+    float c = dot(offset, offset) - earthRadius * earthRadius;
+    float discriminant = b * b - c;
+    if (discriminant < 0.0) {
+        return -1.0;
+    }
+    return -b - sqrt(discriminant);
+}
+
+// A render position in meters as grid coordinates: ground range along its azimuth and height above
+// the curved surface. The angle is taken with atan rather than acos, which loses the small central
+// angles within the grid to rounding.
+vec3 grid_position(vec3 position) {
+    float horizontal = length(position.xz);
+    float vertical = position.y + earthRadius;
+    float groundRange = earthRadius * atan(horizontal, vertical);
+    float height = length(vec2(horizontal, vertical)) - earthRadius;
+    vec2 azimuth = horizontal > 0.0 ? position.xz / horizontal : vec2(0.0);
+    return vec3(azimuth.x * groundRange, height, azimuth.y * groundRange);
+}
+
+void main() {
+    vec3 origin = cameraPosition / renderRatio;
+    vec3 direction = normalize(pos / renderRatio - origin);
+
+    vec2 span = box_intersection(origin, direction);
+    float start = max(span.x, 0.0);
+    float end = span.y;
+
+    float earth = earth_intersection(origin, direction);
+    if (earth > 0.0) {
+        end = min(end, earth);
+    }
+
+    // Composite front to back, stopping once nearly opaque
+    vec4 accumulated = vec4(0.0);
+    float t = start;
+    for (int i = 0; i < MAX_STEPS && t < end && accumulated.a < 0.99; i++) {
+        vec3 uvw = (grid_position(origin + direction * t) - gridMin) / gridSize;
+        t += stepSize;
+
+        if (any(lessThan(uvw, vec3(0.0))) || any(greaterThan(uvw, vec3(1.0)))) {
+            continue;
+        }
+
+        // The value is weighted by coverage, the fraction of the filtered cells with data
+        vec2 cell = texture(values, uvw).rg;
+        if (cell.g <= 0.0) {
+            continue;
+        }
+        vec4 sampled = texture(transferFunction, vec2(cell.r / cell.g, 0.5));
+
+        // Opacities are per reference step, corrected so the look doesn't depend on step size
+        float opacity = clamp(sampled.a * opacityScale * cell.g, 0.0, 1.0);
+        float alpha = 1.0 - pow(1.0 - opacity, stepSize / referenceStep);
+
+        accumulated.rgb += (1.0 - accumulated.a) * alpha * sampled.rgb;
+        accumulated.a += (1.0 - accumulated.a) * alpha;
+    }
+
+    if (accumulated.a <= 0.0) {
+        discard;
+    }
+
+    outColor = vec4(color_mapping(accumulated.rgb / accumulated.a), accumulated.a);
+}
@@ -0,0 +1,335 @@
+use crate::data::ColoredPoint;
+use crate::RENDER_RATIO_TO_M;
+use std::collections::HashMap;
+use three_d::{InnerSpace, Vector3};
+
+type Cell = [i32; 3];
+
+/// A uniform grid over point positions in physical meters (render coordinates unscaled), answering
+/// radius and k-nearest-neighbor queries by only visiting nearby cells. Indices refer to the slice
+/// the index was built from.
+pub struct SpatialIndex {
+    cell_size_m: f32,
+    positions: Vec<Vector3<f32>>,
+    cells: HashMap<Cell, Vec<usize>>,
+    min_cell: Cell,
+    max_cell: Cell,
+}
+
+impl SpatialIndex {
+    /// Indexes the points' positions with the specified cell size, which is best near the typical
+    /// query radius.
+    pub fn new(points: &[ColoredPoint], cell_size_m: f32) -> Self {
+        Self::from_positions(
+            points
+                .iter()
+                .map(|point| point.pos / RENDER_RATIO_TO_M)
+                .collect(),
+            cell_size_m,
+        )
+    }
+
+    /// Indexes arbitrary positions in meters, e.g. gates in a different coordinate system.
+    pub fn from_positions(positions: Vec<Vector3<f32>>, cell_size_m: f32) -> Self {
+        let mut index = SpatialIndex {
+            cell_size_m,
+            positions: Vec::new(),
+            cells: HashMap::new(),
+            min_cell: [i32::MAX; 3],
+            max_cell: [i32::MIN; 3],
+        };
+
+        for (point_index, position) in positions.iter().enumerate() {
+            let cell = index.cell(*position);
+            index.cells.entry(cell).or_default().push(point_index);
+
+            for (axis, cell_axis) in cell.into_iter().enumerate() {
+                index.min_cell[axis] = index.min_cell[axis].min(cell_axis);
+                index.max_cell[axis] = index.max_cell[axis].max(cell_axis);
+            }
+        }
+
+        index.positions = positions;
+        index
+    }
+
+    /// A point's indexed position in meters.
+    pub fn position(&self, index: usize) -> Vector3<f32> {
+        self.positions[index]
+    }
+
+    /// Indices of the points within the radius of the center, including any point at the center.
+    pub fn within_radius(&self, center: Vector3<f32>, radius_m: f32) -> Vec<usize> {
+        let mut found = Vec::new();
+        self.for_each_within_radius(center, radius_m, |index| found.push(index));
+        found
+    }
+
+    /// Indices and distances of the `k` points nearest the center, closest first, optionally limited
+    /// to a maximum distance.
+    pub fn nearest(
+        &self,
+        center: Vector3<f32>,
+        k: usize,
+        max_distance_m: Option<f32>,
+    ) -> Vec<(usize, f32)> {
+        let mut found: Vec<(usize, f32)> = Vec::new();
+        if k == 0 || self.positions.is_empty() {
+            return found;
+        }
+
+        let center_cell = self.cell(center);
+        let (first_shell, max_shell) = (0..3)
+            .map(|axis| {
+                let below = self.min_cell[axis] - center_cell[axis];
+                let above = center_cell[axis] - self.max_cell[axis];
+                (below.max(above).max(0), (-below).max(-above))
+            })
+            .fold((0, 0), |(first, last), (axis_first, axis_last)| {
+                (first.max(axis_first), last.max(axis_last))
+            });
+
+        // Visit cubic shells of cells outward, starting at the first to reach the indexed cells;
+        // points in shell n+1 are at least n cells away, so the search ends once the kth point is
+        // closer than that
+        for shell in first_shell..=max_shell {
+            let shell_distance = (shell - 1).max(0) as f32 * self.cell_size_m;
+            if max_distance_m.is_some_and(|max| shell_distance > max)
+                || found.len() == k && found[k - 1].1 <= shell_distance
+            {
+                break;
+            }
+
+            for cell in self.shell_cells(center_cell, shell) {
+                for &index in self.cells.get(&cell).into_iter().flatten() {
+                    let distance = (self.positions[index] - center).magnitude();
+                    if max_distance_m.is_some_and(|max| distance > max) {
+                        continue;
+                    }
+
+                    let position = found.partition_point(|(_, found)| *found <= distance);
+                    if position < k {
+                        found.insert(position, (index, distance));
+                        found.truncate(k);
+                    }
+                }
+            }
+        }
+
+        found
+    }
+
+    /// Visits the indices of the points within the radius of the center without collecting them.
+    pub fn for_each_within_radius(
+        &self,
+        center: Vector3<f32>,
+        radius_m: f32,
+        mut visit: impl FnMut(usize),
+    ) {
+        let reach = (radius_m / self.cell_size_m).ceil() as i32;
+        let center_cell = self.cell(center);
+
+        for x in (center_cell[0] - reach).max(self.min_cell[0])
+            ..=(center_cell[0] + reach).min(self.max_cell[0])
+        {
+            for y in (center_cell[1] - reach).max(self.min_cell[1])
+                ..=(center_cell[1] + reach).min(self.max_cell[1])
+            {
+                for z in (center_cell[2] - reach).max(self.min_cell[2])
+                    ..=(center_cell[2] + reach).min(self.max_cell[2])
+                {
+                    for &index in self.cells.get(&[x, y, z]).into_iter().flatten() {
+                        if (self.positions[index] - center).magnitude2() <= radius_m * radius_m {
+                            visit(index);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn cell(&self, position: Vector3<f32>) -> Cell {
+        [position.x, position.y, position.z].map(|axis| (axis / self.cell_size_m).floor() as i32)
+    }
+
+    /// The cells on the surface of the cube of cells `shell` cells out from the center cell,
+    /// skipping any outside the indexed cells.
+    fn shell_cells(&self, center: Cell, shell: i32) -> impl Iterator<Item = Cell> {
+        let min_cell = self.min_cell;
+        let max_cell = self.max_cell;
+        let clipped = move |axis: usize| {
+            (center[axis] - shell).max(min_cell[axis])..=(center[axis] + shell).min(max_cell[axis])
+        };
+
+        clipped(0).flat_map(move |x| {
+            clipped(1).flat_map(move |y| {
+                // On the cube's x or y faces every z is on the surface; elsewhere only its z faces
+                let zs = if (x - center[0]).abs() == shell || (y - center[1]).abs() == shell {
+                    clipped(2).collect::<Vec<_>>()
+                } else {
+                    [center[2] - shell, center[2] + shell]
+                        .into_iter()
+                        .filter(|z| (min_cell[2]..=max_cell[2]).contains(z))
+                        .collect()
+                };
+                zs.into_iter().map(move |z| [x, y, z])
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use three_d::vec3;
+
+    /// Two tight clumps and a sparse scatter across a wide area, so most cells are empty.
+    fn positions() -> Vec<Vector3<f32>> {
+        // A fixed linear congruential generator keeps the set the same from run to run
+        let mut state = 12345u32;
+        let mut random = move || {
+            state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+            (state >> 8) as f32 / (1 << 24) as f32
+        };
+
+        let mut positions = Vec::new();
+        for (center, spread, count) in [
+            (vec3(0.0, 0.0, 0.0), 3000.0, 40),
+            (vec3(20000.0, 5000.0, -15000.0), 2000.0, 30),
+            (vec3(0.0, 0.0, 0.0), 60000.0, 30),
+        ] {
+            for _ in 0..count {
+                let offset = vec3(random() - 0.5, random() - 0.5, random() - 0.5) * spread;
+                positions.push(center + offset);
+            }
+        }
+        positions
+    }
+
+    fn brute_force_nearest(
+        positions: &[Vector3<f32>],
+        center: Vector3<f32>,
+        k: usize,
+        max_distance_m: Option<f32>,
+    ) -> Vec<(usize, f32)> {
+        let mut found = positions
+            .iter()
+            .enumerate()
+            .map(|(index, position)| (index, (position - center).magnitude()))
+            .filter(|(_, distance)| max_distance_m.is_none_or(|max| *distance <= max))
+            .collect::<Vec<_>>();
+        found.sort_by(|a, b| a.1.total_cmp(&b.1));
+        found.truncate(k);
+        found
+    }
+
+    fn brute_force_within_radius(
+        positions: &[Vector3<f32>],
+        center: Vector3<f32>,
+        radius_m: f32,
+    ) -> Vec<usize> {
+        (0..positions.len())
+            .filter(|&index| (positions[index] - center).magnitude2() <= radius_m * radius_m)
+            .collect()
+    }
+
+    fn centers() -> Vec<Vector3<f32>> {
+        vec![
+            vec3(0.0, 0.0, 0.0),
+            vec3(1234.5, -678.9, 250.0),
+            vec3(20000.0, 5000.0, -15000.0),
+            vec3(-25000.0, 10000.0, 25000.0),
+            // Outside the indexed points entirely
+            vec3(90000.0, 0.0, 0.0),
+        ]
+    }
+
+    #[test]
+    fn nearest_matches_brute_force() {
+        let positions = positions();
+
+        for cell_size_m in [1000.0, 2500.0, 10000.0] {
+            let index = SpatialIndex::from_positions(positions.clone(), cell_size_m);
+
+            for center in centers() {
+                for k in [1, 5, 9, positions.len(), positions.len() + 10] {
+                    for max_distance_m in [None, Some(1000.0), Some(8000.0), Some(50000.0)] {
+                        assert_eq!(
+                            index.nearest(center, k, max_distance_m),
+                            brute_force_nearest(&positions, center, k, max_distance_m),
+                            "cell size {}, center {:?}, k {}, max {:?}",
+                            cell_size_m,
+                            center,
+                            k,
+                            max_distance_m
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn within_radius_matches_brute_force() {
+        let positions = positions();
+
+        for cell_size_m in [1000.0, 2500.0, 10000.0] {
+            let index = SpatialIndex::from_positions(positions.clone(), cell_size_m);
+
+            for center in centers() {
+                for radius_m in [0.0, 100.0, 1500.0, 7000.0, 100000.0] {
+                    let mut found = index.within_radius(center, radius_m);
+                    found.sort_unstable();
+
+                    assert_eq!(
+                        found,
+                        brute_force_within_radius(&positions, center, radius_m),
+                        "cell size {}, center {:?}, radius {}",
+                        cell_size_m,
+                        center,
+                        radius_m
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn points_exactly_at_the_radius_are_included() {
+        // 3-4-5 triangles, so the distances are exact
+        let positions = vec![
+            vec3(3000.0, 0.0, 4000.0),
+            vec3(-4000.0, 3000.0, 0.0),
+            vec3(0.0, 0.0, 0.0),
+            vec3(6000.0, 0.0, 8000.0),
+        ];
+        let index = SpatialIndex::from_positions(positions, 1000.0);
+        let center = vec3(0.0, 0.0, 0.0);
+
+        let mut found = index.within_radius(center, 5000.0);
+        found.sort_unstable();
+        assert_eq!(found, vec![0, 1, 2]);
+
+        // Points at the same distance may come back in either order
+        let mut nearest = index.nearest(center, 10, Some(5000.0));
+        nearest.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+        assert_eq!(nearest, vec![(2, 0.0), (0, 5000.0), (1, 5000.0)]);
+        assert_eq!(index.nearest(center, 10, Some(4999.0)), vec![(2, 0.0)]);
+    }
+
+    #[test]
+    fn empty_index_finds_nothing() {
+        let index = SpatialIndex::from_positions(Vec::new(), 1000.0);
+        let center = vec3(0.0, 0.0, 0.0);
+
+        assert!(index.within_radius(center, 10000.0).is_empty());
+        assert!(index.nearest(center, 5, None).is_empty());
+    }
+
+    #[test]
+    fn nearest_with_k_zero_finds_nothing() {
+        let index = SpatialIndex::from_positions(positions(), 1000.0);
+
+        assert!(index.nearest(vec3(0.0, 0.0, 0.0), 0, None).is_empty());
+    }
+}
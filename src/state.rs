@@ -1,9 +1,37 @@
+use crate::data::VolumeMetadata;
+use crate::grid::Grid;
+use crate::result::{Error, Result};
 use crate::ColoredPoint;
+use tokio_util::sync::CancellationToken;
 
 pub struct State {
     pub processing: bool,
+    /// Cancels the in-flight fetch/process job, if any, when a newer request supersedes it.
+    pub job: Option<CancellationToken>,
     pub points: Option<Vec<ColoredPoint>>,
+    pub metadata: Option<VolumeMetadata>,
+    pub grid: Option<Grid>,
     pub statistics: Option<ProcessingStatistics>,
+    pub error: Option<ProcessingError>,
+}
+
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+pub enum ProcessingStage {
+    Load,
+    Decompress,
+    Decode,
+    Pointing,
+    Gridding,
+    Sampling,
+    Clustering,
+    Coloring,
+}
+
+/// An error which ended a fetch/process run, along with the stage it occurred in.
+#[derive(Debug)]
+pub struct ProcessingError {
+    pub stage: ProcessingStage,
+    pub error: Error,
 }
 
 #[derive(Default)]
@@ -12,6 +40,17 @@ pub struct ProcessingStatistics {
     pub decompress_ms: u128,
     pub decode_ms: u128,
     pub pointing_ms: u128,
+    pub gridding_ms: u128,
     pub sampling_ms: u128,
+    pub clustering_ms: u128,
     pub coloring_ms: u128,
 }
+
+/// Returns a cancellation error if the job has been superseded, for checking between stages.
+pub fn check_cancelled(cancel: &CancellationToken) -> Result<()> {
+    if cancel.is_cancelled() {
+        Err(Error::CancelledError)
+    } else {
+        Ok(())
+    }
+}
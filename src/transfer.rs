@@ -0,0 +1,322 @@
+use crate::color_table::ColorTable;
+use crate::data::RgbColor;
+use crate::param::{Moment, ThresholdKind, TransferPreset};
+use crate::result::{Error, Result};
+use std::fs;
+use std::path::Path;
+
+/// Opacity of the presets' most opaque values.
+const MAX_OPACITY: f32 = 0.5;
+
+/// Strength above which the cores preset becomes visible.
+const CORES_MIN_STRENGTH: f32 = 0.5;
+
+/// A transfer function's color and opacity at a value.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub struct ControlPoint {
+    /// Value in the moment's units.
+    pub value: f32,
+    pub color: RgbColor,
+    /// Opacity from 0 to 1 over the volume renderer's reference step.
+    pub opacity: f32,
+}
+
+/// Color and opacity by value for volume rendering, interpolated linearly between control points
+/// in ascending value order. Values beyond the first or last point take its color and opacity.
+#[derive(PartialEq, Clone, Debug)]
+pub struct TransferFunction {
+    pub points: Vec<ControlPoint>,
+}
+
+impl TransferFunction {
+    /// A preset for the moment, with points at the ends of its value range and the color table's
+    /// levels between. The color table presets take the table's alpha as well as its colors.
+    pub fn preset(preset: TransferPreset, moment: Moment, table: &ColorTable) -> Self {
+        let (min, max) = moment.value_range();
+
+        let mut values = vec![min, max];
+        values.extend(
+            table
+                .levels()
+                .into_iter()
+                .filter(|level| *level > min && *level < max),
+        );
+        if moment.threshold_kind() == ThresholdKind::Magnitude && min < 0.0 && max > 0.0 {
+            values.push(0.0);
+        }
+        values.sort_by(f32::total_cmp);
+        values.dedup();
+
+        let points = values
+            .into_iter()
+            .map(|value| {
+                let strength = strength(moment, value);
+                let (red, green, blue, alpha) = table.color(value).unwrap_or((0, 0, 0, 255));
+                let table_opacity = MAX_OPACITY * alpha as f32 / 255.0;
+
+                let (color, opacity) = match preset {
+                    TransferPreset::ColorTable => {
+                        ((red, green, blue), table_opacity * strength * strength)
+                    }
+                    TransferPreset::Cores => (
+                        (red, green, blue),
+                        table_opacity
+                            * ((strength - CORES_MIN_STRENGTH) / (1.0 - CORES_MIN_STRENGTH))
+                                .max(0.0),
+                    ),
+                    TransferPreset::Grayscale => {
+                        let gray = (strength * 255.0).round() as u8;
+                        ((gray, gray, gray), MAX_OPACITY * strength)
+                    }
+                };
+
+                ControlPoint {
+                    value,
+                    color,
+                    opacity,
+                }
+            })
+            .collect();
+
+        TransferFunction { points }
+    }
+
+    /// The interpolated color and opacity at a value.
+    pub fn sample(&self, value: f32) -> (RgbColor, f32) {
+        let next = self.points.partition_point(|point| point.value <= value);
+        if next == 0 {
+            return (self.points[0].color, self.points[0].opacity);
+        }
+        if next == self.points.len() {
+            let last = self.points[next - 1];
+            return (last.color, last.opacity);
+        }
+
+        let (from, to) = (self.points[next - 1], self.points[next]);
+        let fraction = (value - from.value) / (to.value - from.value);
+        let mix =
+            |from: u8, to: u8| (from as f32 + (to as f32 - from as f32) * fraction).round() as u8;
+
+        (
+            (
+                mix(from.color.0, to.color.0),
+                mix(from.color.1, to.color.1),
+                mix(from.color.2, to.color.2),
+            ),
+            from.opacity + (to.opacity - from.opacity) * fraction,
+        )
+    }
+
+    /// Reads a transfer function saved by `save`, which must have been saved for the moment if
+    /// it names one in its header.
+    pub fn load(path: &Path, moment: Moment) -> Result<Self> {
+        Self::parse(&fs::read_to_string(path)?, moment)
+    }
+
+    fn parse(text: &str, moment: Moment) -> Result<Self> {
+        let mut points = Vec::new();
+        for (line_index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if let Some(comment) = line.strip_prefix('#') {
+                if let Some((abbreviation, _)) = comment.split_once("transfer function:") {
+                    let abbreviation = abbreviation.trim();
+                    if abbreviation != moment.abbreviation() {
+                        return Err(Error::TransferFunctionError(format!(
+                            "saved for {}, not {}",
+                            abbreviation,
+                            moment.abbreviation()
+                        )));
+                    }
+                }
+                continue;
+            }
+            if line.is_empty() {
+                continue;
+            }
+
+            points.push(parse_point(line).ok_or_else(|| {
+                Error::TransferFunctionError(format!(
+                    "line {} is not \"value red green blue opacity\": {}",
+                    line_index + 1,
+                    line
+                ))
+            })?);
+        }
+
+        if points.len() < 2 {
+            return Err(Error::TransferFunctionError(
+                "at least two control points are required".to_string(),
+            ));
+        }
+
+        points.sort_by(|a, b| a.value.total_cmp(&b.value));
+        Ok(TransferFunction { points })
+    }
+
+    /// Writes the control points as lines of value, red, green, blue, and opacity.
+    pub fn save(&self, path: &Path, moment: Moment) -> Result<()> {
+        let mut text = format!(
+            "# {} transfer function: value ({}) red green blue opacity\n",
+            moment.abbreviation(),
+            moment.units()
+        );
+
+        for point in &self.points {
+            text.push_str(&format!(
+                "{} {} {} {} {}\n",
+                point.value, point.color.0, point.color.1, point.color.2, point.opacity
+            ));
+        }
+
+        fs::write(path, text)?;
+        Ok(())
+    }
+}
+
+/// How strong a value is from 0 to 1 across the moment's value range, growing toward both ends
+/// for moments thresholded by magnitude.
+fn strength(moment: Moment, value: f32) -> f32 {
+    let (min, max) = moment.value_range();
+
+    let strength = match moment.threshold_kind() {
+        ThresholdKind::Minimum => (value - min) / (max - min),
+        ThresholdKind::Magnitude => value.abs() / min.abs().max(max.abs()),
+    };
+
+    strength.clamp(0.0, 1.0)
+}
+
+fn parse_point(line: &str) -> Option<ControlPoint> {
+    let fields = line.split_whitespace().collect::<Vec<_>>();
+    let [value, red, green, blue, opacity] = fields.as_slice() else {
+        return None;
+    };
+
+    let value = value
+        .parse::<f32>()
+        .ok()
+        .filter(|value| value.is_finite())?;
+    let opacity = opacity
+        .parse::<f32>()
+        .ok()
+        .filter(|opacity| (0.0..=1.0).contains(opacity))?;
+
+    Some(ControlPoint {
+        value,
+        color: (red.parse().ok()?, green.parse().ok()?, blue.parse().ok()?),
+        opacity,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn point(value: f32, color: RgbColor, opacity: f32) -> ControlPoint {
+        ControlPoint {
+            value,
+            color,
+            opacity,
+        }
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let transfer_function = TransferFunction {
+            points: vec![
+                point(-10.0, (0, 0, 0), 0.0),
+                point(22.5, (10, 200, 30), 0.125),
+                point(75.0, (255, 255, 255), 0.5),
+            ],
+        };
+        let path = env::temp_dir().join(format!("transfer-round-trip-{}.txt", std::process::id()));
+
+        transfer_function.save(&path, Moment::Reflectivity).unwrap();
+        let loaded = TransferFunction::load(&path, Moment::Reflectivity);
+        let mismatched = TransferFunction::load(&path, Moment::Velocity);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.unwrap(), transfer_function);
+        assert!(matches!(
+            mismatched,
+            Err(Error::TransferFunctionError(message)) if message == "saved for REF, not VEL"
+        ));
+    }
+
+    #[test]
+    fn files_without_a_header_load_for_any_moment() {
+        let text = "0 0 0 0 0\n10 255 255 255 1\n";
+
+        for moment in Moment::ALL {
+            assert!(TransferFunction::parse(text, moment).is_ok());
+        }
+    }
+
+    #[test]
+    fn fewer_than_two_points_are_rejected() {
+        for text in [
+            "",
+            "# REF transfer function: value (dBZ) red green blue opacity\n",
+            "5 1 2 3 0.5\n",
+        ] {
+            assert!(matches!(
+                TransferFunction::parse(text, Moment::Reflectivity),
+                Err(Error::TransferFunctionError(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn malformed_points_are_rejected() {
+        for line in ["5 1 2 3", "5 1 2 3 1.5", "inf 1 2 3 0.5", "5 1 2 300 0.5"] {
+            let text = format!("0 0 0 0 0\n{}\n", line);
+            assert!(matches!(
+                TransferFunction::parse(&text, Moment::Reflectivity),
+                Err(Error::TransferFunctionError(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn points_are_sorted_by_value() {
+        let transfer_function = TransferFunction::parse(
+            "30 3 3 3 0.3\n-5 1 1 1 0.1\n10 2 2 2 0.2\n",
+            Moment::Reflectivity,
+        )
+        .unwrap();
+
+        assert_eq!(
+            transfer_function.points,
+            vec![
+                point(-5.0, (1, 1, 1), 0.1),
+                point(10.0, (2, 2, 2), 0.2),
+                point(30.0, (3, 3, 3), 0.3),
+            ]
+        );
+    }
+
+    #[test]
+    fn sample_interpolates_between_points() {
+        let transfer_function = TransferFunction {
+            points: vec![
+                point(0.0, (0, 100, 200), 0.0),
+                point(10.0, (100, 100, 0), 0.5),
+                point(20.0, (200, 0, 0), 1.0),
+            ],
+        };
+
+        // At the control points
+        assert_eq!(transfer_function.sample(0.0), ((0, 100, 200), 0.0));
+        assert_eq!(transfer_function.sample(10.0), ((100, 100, 0), 0.5));
+        assert_eq!(transfer_function.sample(20.0), ((200, 0, 0), 1.0));
+
+        // Between them
+        assert_eq!(transfer_function.sample(2.5), ((25, 100, 150), 0.125));
+        assert_eq!(transfer_function.sample(15.0), ((150, 50, 0), 0.75));
+
+        // Beyond the ends
+        assert_eq!(transfer_function.sample(-50.0), ((0, 100, 200), 0.0));
+        assert_eq!(transfer_function.sample(50.0), ((200, 0, 0), 1.0));
+    }
+}
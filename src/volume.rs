@@ -0,0 +1,217 @@
+use crate::beam::render_position;
+use crate::grid::Grid;
+use crate::object::EARTH_RADIUS_M;
+use crate::param::{Moment, VisParams};
+use crate::transfer::TransferFunction;
+use crate::RENDER_RATIO_TO_M;
+use std::f32::consts::SQRT_2;
+use three_d::{
+    vec3, Blend, Camera, ColorMapping, Context, CpuTexture, CpuTexture3D, Cull, DepthTest,
+    FragmentAttributes, Interpolation, Light, Material, MaterialType, Program, RenderStates, Srgba,
+    Texture2D, Texture3D, TextureData, Vec3, Wrapping, WriteMask,
+};
+
+/// Entries in the transfer function's lookup texture, evenly spanning the moment's value range.
+const TRANSFER_FUNCTION_SIZE: usize = 256;
+
+/// Path length in meters the transfer function's opacities apply over; samples at other step sizes
+/// are corrected to match.
+const REFERENCE_STEP_M: f32 = 1000.0;
+
+/// Must be unique among materials and below the ids three-d reserves for its own.
+const VOLUME_MATERIAL_ID: u16 = 0b1;
+
+/// Ray-marches a grid uploaded as a 3D texture, compositing each sample's transfer function color
+/// and opacity front to back. Apply to a cube spanning `render_bounds`, whose back faces start the
+/// rays so they are drawn with the camera inside the volume too. Samples are mapped onto the grid
+/// through the curved earth, and rays stop at the earth's surface.
+pub struct VolumeMaterial {
+    /// Per cell, the value normalized to the moment's value range and 1 if it has data, both 0
+    /// otherwise, so linear filtering weights only cells with data.
+    values: Texture3D,
+    moment: Moment,
+    /// The transfer function sampled across the moment's value range in linear color.
+    lookup_table: Texture2D,
+    transfer_function: TransferFunction,
+    grid_min_m: Vec3,
+    grid_size_m: Vec3,
+    bounds: (Vec3, Vec3),
+    step_m: f32,
+    opacity_scale: f32,
+}
+
+impl VolumeMaterial {
+    pub fn new(context: &Context, grid: &Grid, vis_params: &VisParams) -> Self {
+        let [width, height, depth] = grid.dimensions;
+        let (min, max) = grid.moment.value_range();
+
+        let mut values = Vec::with_capacity(width * height * depth);
+        for z in 0..depth {
+            for y in 0..height {
+                for x in 0..width {
+                    let value = grid.value([x, y, z]);
+                    values.push(if value.is_nan() {
+                        [0.0, 0.0]
+                    } else {
+                        [((value - min) / (max - min)).clamp(0.0, 1.0), 1.0]
+                    });
+                }
+            }
+        }
+
+        let values = Texture3D::new(
+            context,
+            &CpuTexture3D {
+                data: TextureData::RgF32(values),
+                width: width as u32,
+                height: height as u32,
+                depth: depth as u32,
+                mip_map_filter: None,
+                wrap_s: Wrapping::ClampToEdge,
+                wrap_t: Wrapping::ClampToEdge,
+                wrap_r: Wrapping::ClampToEdge,
+                ..Default::default()
+            },
+        );
+
+        let lookup_table = Texture2D::new(
+            context,
+            &CpuTexture {
+                data: TextureData::RgbaF32(lookup_table(
+                    &vis_params.transfer_function,
+                    grid.moment,
+                )),
+                width: TRANSFER_FUNCTION_SIZE as u32,
+                height: 1,
+                mip_map_filter: None,
+                wrap_s: Wrapping::ClampToEdge,
+                wrap_t: Wrapping::ClampToEdge,
+                min_filter: Interpolation::Linear,
+                mag_filter: Interpolation::Linear,
+                ..Default::default()
+            },
+        );
+
+        let cell_size_m = vec3(
+            grid.horizontal_resolution_m,
+            grid.vertical_resolution_m,
+            grid.horizontal_resolution_m,
+        );
+        let grid_min_m = grid.origin_m - cell_size_m / 2.0;
+        let grid_size_m = vec3(
+            width as f32 * cell_size_m.x,
+            height as f32 * cell_size_m.y,
+            depth as f32 * cell_size_m.z,
+        );
+
+        VolumeMaterial {
+            values,
+            moment: grid.moment,
+            lookup_table,
+            transfer_function: vis_params.transfer_function.clone(),
+            grid_min_m,
+            grid_size_m,
+            bounds: render_bounds(grid_min_m, grid_size_m),
+            step_m: vis_params.volume_step_m,
+            opacity_scale: vis_params.volume_opacity_scale,
+        }
+    }
+
+    /// Applies the visualization parameters' step size, opacity scale, and transfer function,
+    /// uploading the transfer function only when it has changed so it can be edited live.
+    pub fn update(&mut self, vis_params: &VisParams) {
+        self.step_m = vis_params.volume_step_m;
+        self.opacity_scale = vis_params.volume_opacity_scale;
+
+        if vis_params.transfer_function != self.transfer_function {
+            self.transfer_function = vis_params.transfer_function.clone();
+            self.lookup_table
+                .fill(&lookup_table(&self.transfer_function, self.moment));
+        }
+    }
+
+    /// Minimum and maximum corners of the render-space box containing the grid.
+    pub fn render_bounds(&self) -> (Vec3, Vec3) {
+        self.bounds
+    }
+}
+
+impl Material for VolumeMaterial {
+    fn fragment_shader_source(&self, _lights: &[&dyn Light]) -> String {
+        let mut source = ColorMapping::fragment_shader_source().to_string();
+        source.push_str(include_str!("shaders/volume.frag"));
+        source
+    }
+
+    fn id(&self) -> u16 {
+        VOLUME_MATERIAL_ID
+    }
+
+    fn fragment_attributes(&self) -> FragmentAttributes {
+        FragmentAttributes {
+            position: true,
+            ..FragmentAttributes::NONE
+        }
+    }
+
+    fn use_uniforms(&self, program: &Program, camera: &Camera, _lights: &[&dyn Light]) {
+        camera.color_mapping.use_uniforms(program);
+        program.use_uniform("cameraPosition", camera.position());
+        program.use_uniform("renderRatio", RENDER_RATIO_TO_M);
+        program.use_uniform("earthRadius", EARTH_RADIUS_M);
+        program.use_uniform("boxMin", self.bounds.0);
+        program.use_uniform("boxMax", self.bounds.1);
+        program.use_uniform("gridMin", self.grid_min_m);
+        program.use_uniform("gridSize", self.grid_size_m);
+        program.use_uniform("stepSize", self.step_m);
+        program.use_uniform("referenceStep", REFERENCE_STEP_M);
+        program.use_uniform("opacityScale", self.opacity_scale);
+        program.use_texture_3d("values", &self.values);
+        program.use_texture("transferFunction", &self.lookup_table);
+    }
+
+    fn render_states(&self) -> RenderStates {
+        // The shader ends rays at the earth itself, so faces behind it must not be depth-culled
+        RenderStates {
+            write_mask: WriteMask::COLOR,
+            depth_test: DepthTest::Always,
+            blend: Blend::TRANSPARENCY,
+            cull: Cull::Front,
+        }
+    }
+
+    fn material_type(&self) -> MaterialType {
+        MaterialType::Transparent
+    }
+}
+
+/// Samples the transfer function evenly across the moment's value range in linear color.
+fn lookup_table(transfer_function: &TransferFunction, moment: Moment) -> Vec<[f32; 4]> {
+    let (min, max) = moment.value_range();
+
+    (0..TRANSFER_FUNCTION_SIZE)
+        .map(|entry| {
+            let fraction = entry as f32 / (TRANSFER_FUNCTION_SIZE - 1) as f32;
+            let (color, opacity) = transfer_function.sample(min + fraction * (max - min));
+            let linear = Srgba::new(color.0, color.1, color.2, 255).to_linear_srgb();
+
+            [linear.x, linear.y, linear.z, opacity]
+        })
+        .collect()
+}
+
+/// The render-space box containing a grid with the specified minimum corner and size in grid
+/// meters. The grid's levels follow the earth's curvature, so the box reaches down to where its
+/// far corners' bottoms drop below the radar.
+fn render_bounds(grid_min_m: Vec3, grid_size_m: Vec3) -> (Vec3, Vec3) {
+    let grid_max_m = grid_min_m + grid_size_m;
+    let reach_m = [grid_min_m.x, grid_max_m.x, grid_min_m.z, grid_max_m.z]
+        .into_iter()
+        .fold(0.0, |reach: f32, edge| reach.max(edge.abs()));
+
+    let reach = render_position(0.0, reach_m, grid_max_m.y).x;
+    let bottom = render_position(0.0, reach_m * SQRT_2, grid_min_m.y).y;
+    let top = grid_max_m.y * RENDER_RATIO_TO_M;
+
+    (vec3(-reach, bottom, -reach), vec3(reach, top, reach))
+}